@@ -0,0 +1,78 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Values for `input(...)` prompts supplied up front instead of typed in.
+///
+/// A value is looked up by variable name, first in `--set name=value`, then
+/// in the answers file, the answers recorded by an earlier run when
+/// replaying it and finally in the `TMPL_VAR_<NAME>` environment variable.
+#[derive(Default)]
+pub struct Answers {
+    sets: HashMap<String, String>,
+    file: HashMap<String, String>,
+    recorded: HashMap<String, String>,
+    pub non_interactive: bool,
+}
+
+impl Answers {
+    pub fn new(sets: &[String], answers_file: Option<&Path>, non_interactive: bool) -> Result<Self, String> {
+        let mut answers = Answers {
+            non_interactive,
+            ..Default::default()
+        };
+
+        for set in sets {
+            let (name, value) = set
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid --set '{}', expected name=value", set))?;
+            answers.sets.insert(name.trim().to_string(), value.to_string());
+        }
+
+        if let Some(path) = answers_file {
+            answers.file = read_answers_file(path)?;
+        }
+
+        Ok(answers)
+    }
+
+    /// Adds the answers recorded by an earlier run, used to replay it.
+    /// Passwords are never recorded, so those still come from the other
+    /// answers or are asked.
+    pub fn with_recorded(mut self, answers: BTreeMap<String, String>) -> Self {
+        self.recorded = answers.into_iter().collect();
+        self
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<String> {
+        self.sets
+            .get(name)
+            .or_else(|| self.file.get(name))
+            .or_else(|| self.recorded.get(name))
+            .cloned()
+            .or_else(|| env::var(format!("TMPL_VAR_{}", name.to_uppercase())).ok())
+    }
+}
+
+/// Reads a flat `name = value` TOML table. Non-string values are converted
+/// to the text they would have been typed as.
+fn read_answers_file(path: &Path) -> Result<HashMap<String, String>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read answers file '{}': {}", path.display(), e))?;
+
+    let table: toml::Table = contents
+        .parse()
+        .map_err(|e| format!("Invalid answers file '{}': {}", path.display(), e))?;
+
+    Ok(table
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                toml::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            (name, value)
+        })
+        .collect())
+}
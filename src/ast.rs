@@ -0,0 +1,187 @@
+use crate::lexer::Span;
+use crate::value::Value;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// A fully parsed template. Function definitions are hoisted out of the body
+/// so they can be called before the line they are declared on.
+pub struct Program {
+    pub functions: HashMap<String, FunctionDefinition>,
+    pub body: Block,
+    /// Parameters declared in the template's manifest, asked for before the
+    /// body runs.
+    pub parameters: Vec<Parameter>,
+    /// The template's `files/` directory, the source of `copy_tree:` and
+    /// `render_tree:`.
+    pub files_dir: Option<PathBuf>,
+    /// Templates named by `import:`, whose functions can be called from
+    /// this one.
+    pub imports: Vec<String>,
+    /// Every template named by `include:` or `import:` and where, so they
+    /// can be loaded before the template runs.
+    pub dependencies: Vec<(String, Span)>,
+}
+
+pub type Block = Vec<Stmt>;
+
+pub struct Parameter {
+    pub name: String,
+    pub prompt: Prompt,
+}
+
+pub struct FunctionDefinition {
+    pub params: Vec<String>,
+    pub body: Block,
+    pub span: Span,
+}
+
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+/// Statements hold their arguments as raw template text; variables are only
+/// substituted when the statement runs.
+pub enum StmtKind {
+    Var { name: String, value: VarValue },
+    Mkdir(String),
+    CreateFile(String),
+    WriteFile { path: String, content: Content },
+    Cd(String),
+    /// `copy_tree: source -> target` copies a directory from the template's
+    /// `files/` into `target`; `render_tree:` also substitutes variables in
+    /// the text files. Names are substituted either way.
+    Tree { source: String, target: String, render: bool },
+    Command(Vec<CommandLine>),
+    /// Runs the body of the first branch whose condition holds, or
+    /// `otherwise` (the `else` block) if none does.
+    If { branches: Vec<Branch>, otherwise: Option<Block> },
+    /// Runs `body` once per value, with the value bound to `var` in a scope
+    /// of its own, like a function parameter.
+    For { var: String, iterable: Iterable, body: Block },
+    While { condition: Expr, body: Block },
+    Call { name: String, args: Vec<String> },
+    /// `include: name` runs another installed template here, sharing the
+    /// variables of this one.
+    Include(String),
+    /// Runs `body`; if a step in it fails, runs `catch` (with the error
+    /// message in `$error`) instead of stopping the template.
+    Try { body: Block, catch: Option<Block> },
+}
+
+/// An `if:` or `else if:` condition and the block it guards.
+pub struct Branch {
+    pub condition: Expr,
+    pub body: Block,
+    pub span: Span,
+}
+
+/// What a `for:` loop iterates over.
+pub enum Iterable {
+    /// `a, b, c`
+    List(Vec<String>),
+    /// `1..5`, both ends included.
+    Range(String, String),
+    /// `glob(src/**/*.rs)`, the matching paths in order.
+    Glob(String),
+    /// `$features` or `[a, b]`: the items of a list or the keys of a map.
+    Value(Expr),
+}
+
+pub enum Content {
+    /// A single line that may contain `\n`, `\t` and `\r` escapes.
+    Inline(String),
+    /// The verbatim body of a `<<EOF ... EOF>>` block.
+    Heredoc(String),
+}
+
+pub struct CommandLine {
+    pub command: String,
+    pub span: Span,
+}
+
+pub enum VarValue {
+    Text(String),
+    Prompt(Prompt),
+}
+
+/// A built-in that asks the user for a value, such as `input(...)` or
+/// `select(...)`.
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub question: String,
+    /// The default as written in the template, empty if there is none.
+    /// Variables in it are substituted when the prompt is reached.
+    pub default: String,
+    /// Rules from a `validate:` clause that every answer must pass.
+    pub validators: Vec<Validator>,
+    /// A `when:` condition; if it doesn't hold, the prompt is skipped.
+    pub when: Option<Expr>,
+}
+
+#[derive(Clone)]
+pub enum Validator {
+    Required,
+    Regex(Regex),
+    MinLen(usize),
+    MaxLen(usize),
+    Range(f64, f64),
+    OneOf(Vec<String>),
+}
+
+pub enum PromptKind {
+    /// `input(question, default)`
+    Text,
+    /// `password(question)`
+    Password,
+    /// `confirm(question, true)`
+    Confirm,
+    /// `number(question, 3000)`
+    Number,
+    /// `select(question, [a, b, c], default)`
+    Select(Expr),
+    /// `multiselect(question, [a, b, c], [defaults])`
+    MultiSelect(Expr),
+}
+
+/// An expression as used in `if:` conditions and typed `var:` values.
+pub enum Expr {
+    /// A double-quoted string; variables in it are substituted.
+    Str(String),
+    /// A single-quoted string or a bare word, taken literally. Bare words
+    /// that are numbers or booleans are read as such.
+    Literal(Value),
+    Var(String),
+    List(Vec<Expr>),
+    Map(Vec<(String, Expr)>),
+    /// `list[0]` or `map["key"]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `map.key`
+    Field(Box<Expr>, String),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+/// A filter in `${value | name(args)}`.
+pub struct Filter {
+    pub name: String,
+    pub args: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Matches,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
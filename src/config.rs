@@ -0,0 +1,57 @@
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// tmpl's own settings, read from `config.toml` in the user's config
+/// directory, e.g. `~/.config/tmpl/config.toml`.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The registries templates are installed from, tried in order.
+    #[serde(default, rename = "registry")]
+    pub registries: Vec<RegistryConfig>,
+    /// Base64 ed25519 public keys. If there are any, only templates signed
+    /// with one of them are installed.
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+}
+
+/// A `[[registry]]` entry. Exactly one of `url`, `path` and `git` says
+/// where the registry is.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    /// How the registry is called in messages, its location if unset.
+    pub name: Option<String>,
+    /// Base URL of a registry served over HTTP(S).
+    pub url: Option<String>,
+    /// GitHub git tree API URL listing an HTTP registry's files, instead of
+    /// its `files.txt` lists.
+    pub tree: Option<String>,
+    /// A registry in a local directory.
+    pub path: Option<PathBuf>,
+    /// A git repository holding the registry, checked out locally.
+    pub git: Option<String>,
+    /// The branch of `git` to use, its default branch if unset.
+    pub branch: Option<String>,
+}
+
+pub fn config_path() -> PathBuf {
+    dirs::config_dir()
+        .expect("Could not find config directory")
+        .join("tmpl/config.toml")
+}
+
+impl Config {
+    /// Reads the config file; all defaults if there is none.
+    pub fn load() -> Result<Self, String> {
+        let path = config_path();
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        toml::from_str(&text).map_err(|e| format!("Invalid {}: {}", path.display(), e.message()))
+    }
+}
@@ -0,0 +1,397 @@
+use crate::manifest::Manifest;
+use crate::parse;
+use crate::registry::Listing;
+use semver::{Version, VersionReq};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where a template in a resolved dependency graph comes from.
+pub enum Origin {
+    /// The template being installed, fetched by the caller.
+    Root,
+    /// Already installed in a version that fits.
+    Installed,
+    /// To be downloaded from the registry.
+    Registry(Box<Listing>),
+}
+
+pub struct Node {
+    pub version: Option<Version>,
+    pub dependencies: Vec<String>,
+    pub origin: Origin,
+}
+
+/// The templates a template depends on, directly or through others, found
+/// before anything is downloaded.
+pub struct Resolution {
+    pub root: String,
+    pub nodes: HashMap<String, Node>,
+    /// Every template in the graph, dependencies before their dependents.
+    pub order: Vec<String>,
+}
+
+/// Finds the newest version of a template matching a requirement; `None`
+/// if there is no such template.
+pub type Lookup<'a> = dyn FnMut(&str, &VersionReq) -> Result<Option<Listing>, String> + 'a;
+
+/// A `[dependencies]` entry: `by` needs a version of `on` matching `req`.
+struct Requirement {
+    by: String,
+    on: String,
+    req: VersionReq,
+    /// Whether it comes from a template that's already installed rather
+    /// than from the graph being resolved.
+    installed: bool,
+}
+
+/// Resolves the dependencies declared in `manifest`, the manifest of the
+/// template `root` that is about to be installed. Dependencies that are
+/// installed in a fitting version are kept; the others are looked up with
+/// `lookup`. Fails on a dependency that can't be found, on cycles, and when
+/// a version would break a requirement of another template in the graph or
+/// of an installed template.
+pub fn resolve(
+    root: &str,
+    manifest: Option<&Manifest>,
+    lookup: &mut Lookup<'_>,
+) -> Result<Resolution, String> {
+    resolve_in(&parse::template_dir(""), root, manifest, lookup)
+}
+
+/// `resolve` against the templates installed in `store`.
+fn resolve_in(
+    store: &Path,
+    root: &str,
+    manifest: Option<&Manifest>,
+    lookup: &mut Lookup<'_>,
+) -> Result<Resolution, String> {
+    let mut resolver = Resolver {
+        lookup,
+        store: store.to_path_buf(),
+        nodes: HashMap::new(),
+        order: Vec::new(),
+        requirements: Vec::new(),
+        stack: vec![root.to_string()],
+    };
+
+    for installed in templates_in(store) {
+        if installed == root {
+            continue;
+        }
+        if let Ok(Some(manifest)) = Manifest::load(&store.join(&installed)) {
+            resolver.require(&installed, &manifest.dependencies, true);
+        }
+    }
+
+    if let Some(manifest) = manifest {
+        resolver.require(root, &manifest.dependencies, false);
+        for (dependency, req) in &manifest.dependencies {
+            resolver.visit(dependency, root, req)?;
+        }
+    }
+
+    resolver.nodes.insert(
+        root.to_string(),
+        Node {
+            version: manifest.map(|m| m.version.clone()),
+            dependencies: manifest.map_or_else(Vec::new, |m| m.dependencies.keys().cloned().collect()),
+            origin: Origin::Root,
+        },
+    );
+    resolver.order.push(root.to_string());
+    resolver.check_conflicts()?;
+
+    Ok(Resolution {
+        root: root.to_string(),
+        nodes: resolver.nodes,
+        order: resolver.order,
+    })
+}
+
+struct Resolver<'a, 'b> {
+    lookup: &'a mut Lookup<'b>,
+    /// Where the installed templates are.
+    store: PathBuf,
+    nodes: HashMap<String, Node>,
+    order: Vec<String>,
+    requirements: Vec<Requirement>,
+    /// The chain of templates being resolved, to find cycles.
+    stack: Vec<String>,
+}
+
+impl Resolver<'_, '_> {
+    fn require(&mut self, by: &str, dependencies: &BTreeMap<String, VersionReq>, installed: bool) {
+        self.requirements.extend(dependencies.iter().map(|(on, req)| Requirement {
+            by: by.to_string(),
+            on: on.clone(),
+            req: req.clone(),
+            installed,
+        }));
+    }
+
+    fn visit(&mut self, name: &str, needed_by: &str, req: &VersionReq) -> Result<(), String> {
+        if let Some(start) = self.stack.iter().position(|resolving| resolving == name) {
+            let cycle: Vec<&str> = self.stack[start..].iter().map(String::as_str).chain([name]).collect();
+            return Err(format!("Dependency cycle: {}", cycle.join(" -> ")));
+        }
+        if self.nodes.contains_key(name) {
+            return Ok(());
+        }
+
+        let installed = Manifest::load(&self.store.join(name)).ok().flatten();
+        let (version, dependencies, origin) = match installed {
+            Some(manifest) if req.matches(&manifest.version) => {
+                (Some(manifest.version), manifest.dependencies, Origin::Installed)
+            }
+            _ => {
+                let Some(listing) = (self.lookup)(name, req).map_err(|e| format!("{} (needed by '{}')", e, needed_by))?
+                else {
+                    return Err(format!("Template '{}', needed by '{}', was not found in the registry", name, needed_by));
+                };
+                let dependencies = listing.manifest.as_ref().map(|m| m.dependencies.clone()).unwrap_or_default();
+                (listing.version.clone(), dependencies, Origin::Registry(Box::new(listing)))
+            }
+        };
+
+        self.require(name, &dependencies, false);
+        self.stack.push(name.to_string());
+        for (dependency, req) in &dependencies {
+            self.visit(dependency, name, req)?;
+        }
+        self.stack.pop();
+
+        self.nodes.insert(
+            name.to_string(),
+            Node {
+                version,
+                dependencies: dependencies.into_keys().collect(),
+                origin,
+            },
+        );
+        self.order.push(name.to_string());
+        Ok(())
+    }
+
+    /// Checks every requirement on a template in the graph against the
+    /// version that will be installed. For installed templates that are part
+    /// of the graph, the requirements of the version in the graph count, and
+    /// templates that stay as they are aren't checked against installed
+    /// ones.
+    fn check_conflicts(&self) -> Result<(), String> {
+        let mut conflicts = Vec::new();
+        for requirement in &self.requirements {
+            if requirement.installed && self.nodes.contains_key(&requirement.by) {
+                continue;
+            }
+            let Some(node) = self.nodes.get(&requirement.on) else {
+                continue;
+            };
+            if requirement.installed && matches!(node.origin, Origin::Installed) {
+                continue;
+            }
+
+            let fits = node.version.as_ref().is_some_and(|version| requirement.req.matches(version));
+            if !fits {
+                let version = node.version.as_ref().map_or("no version".to_string(), Version::to_string);
+                conflicts.push(format!(
+                    "'{}'{} needs {} {}, but {} would be installed",
+                    requirement.by,
+                    if requirement.installed { " (installed)" } else { "" },
+                    requirement.on,
+                    requirement.req,
+                    version
+                ));
+            }
+        }
+
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Version conflict:\n  {}", conflicts.join("\n  ")))
+        }
+    }
+}
+
+impl Resolution {
+    /// Prints the graph as a tree below the root.
+    pub fn print_tree(&self) {
+        self.print_node(&self.root, "", "");
+    }
+
+    fn print_node(&self, name: &str, first_prefix: &str, prefix: &str) {
+        let Some(node) = self.nodes.get(name) else {
+            return;
+        };
+        let version = node.version.as_ref().map_or(String::new(), |v| format!(" {}", v));
+        let note = match node.origin {
+            Origin::Installed => " \x1b[90m(already installed)\x1b[0m",
+            _ => "",
+        };
+        println!("{}{}{}{}", first_prefix, name, version, note);
+
+        for (i, dependency) in node.dependencies.iter().enumerate() {
+            let last = i + 1 == node.dependencies.len();
+            let (branch, indent) = if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+            self.print_node(dependency, &format!("{}{}", prefix, branch), &format!("{}{}", prefix, indent));
+        }
+    }
+}
+
+/// A requirement that only `version` itself matches.
+pub fn exactly(version: &Version) -> VersionReq {
+    VersionReq {
+        comparators: vec![semver::Comparator {
+            op: semver::Op::Exact,
+            major: version.major,
+            minor: Some(version.minor),
+            patch: Some(version.patch),
+            pre: version.pre.clone(),
+        }],
+    }
+}
+
+/// The names of all installed templates.
+pub fn installed_templates() -> Vec<String> {
+    templates_in(&parse::template_dir(""))
+}
+
+fn templates_in(store: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(store) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    names.sort();
+    names
+}
+
+/// The installed templates that depend on `name`, through their manifest or
+/// an `include:` or `import:` in their script.
+pub fn dependents(name: &str) -> Vec<String> {
+    installed_templates()
+        .into_iter()
+        .filter(|installed| installed != name)
+        .filter(|installed| {
+            let dir = parse::template_dir(installed);
+            let in_manifest = Manifest::load(&dir)
+                .ok()
+                .flatten()
+                .is_some_and(|manifest| manifest.dependencies.contains_key(name));
+            let in_script = parse::parse_file(installed).is_ok_and(|text| {
+                text.lines().any(|line| {
+                    let line = line.trim();
+                    let target = line.strip_prefix("include:").or_else(|| line.strip_prefix("import:"));
+                    target.is_some_and(|target| target.trim() == name)
+                })
+            });
+            in_manifest || in_script
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::MANIFEST_FILE;
+    use tempfile::TempDir;
+
+    /// A template version in the registry: name, version and dependencies.
+    type Published<'a> = (&'a str, &'a str, &'a [(&'a str, &'a str)]);
+
+    fn manifest_text(name: &str, version: &str, dependencies: &[(&str, &str)]) -> String {
+        let mut text = format!("name = \"{}\"\nversion = \"{}\"\n[dependencies]\n", name, version);
+        for (on, req) in dependencies {
+            text.push_str(&format!("{} = \"{}\"\n", on, req));
+        }
+        text
+    }
+
+    fn manifest(name: &str, version: &str, dependencies: &[(&str, &str)]) -> Manifest {
+        Manifest::parse(&manifest_text(name, version, dependencies)).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Resolves `root` with `dependencies` against a registry holding
+    /// `published`, with nothing installed unless `store` has it.
+    fn resolve_with(
+        store: &Path,
+        dependencies: &[(&str, &str)],
+        published: &[Published],
+    ) -> Result<Resolution, String> {
+        let root = manifest("root", "1.0.0", dependencies);
+        let mut lookup = |name: &str, req: &VersionReq| -> Result<Option<Listing>, String> {
+            Ok(published
+                .iter()
+                .map(|(published, version, dependencies)| manifest(published, version, dependencies))
+                .filter(|m| m.name == name && req.matches(&m.version))
+                .max_by(|a, b| a.version.cmp(&b.version))
+                .map(Listing::from_manifest))
+        };
+        resolve_in(store, "root", Some(&root), &mut lookup)
+    }
+
+    fn install(store: &Path, name: &str, version: &str, dependencies: &[(&str, &str)]) {
+        let dir = store.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), manifest_text(name, version, dependencies)).unwrap();
+        fs::write(dir.join("file.tmpl"), "").unwrap();
+    }
+
+    #[test]
+    fn dependencies_come_before_their_dependents() {
+        let store = TempDir::new().unwrap();
+        let resolution = resolve_with(
+            store.path(),
+            &[("api", "^1")],
+            &[("api", "1.0.0", &[]), ("api", "1.4.0", &[("base", ">=0.3")]), ("base", "0.3.2", &[])],
+        )
+        .unwrap();
+
+        assert_eq!(resolution.order, ["base", "api", "root"]);
+        assert_eq!(resolution.nodes["api"].version, Some(Version::new(1, 4, 0)));
+    }
+
+    #[test]
+    fn cycles_are_refused() {
+        let store = TempDir::new().unwrap();
+        let error = resolve_with(
+            store.path(),
+            &[("a", "*")],
+            &[("a", "1.0.0", &[("b", "*")]), ("b", "1.0.0", &[("a", "*")])],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error, "Dependency cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn conflicting_requirements_are_refused() {
+        let store = TempDir::new().unwrap();
+        let error = resolve_with(
+            store.path(),
+            &[("a", "^1"), ("b", "^1")],
+            &[("a", "1.5.0", &[]), ("a", "2.0.0", &[]), ("b", "1.0.0", &[("a", "^2")])],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error, "Version conflict:\n  'b' needs a ^2, but 1.5.0 would be installed");
+    }
+
+    #[test]
+    fn installed_templates_count() {
+        let store = TempDir::new().unwrap();
+        install(store.path(), "a", "1.1.0", &[]);
+        install(store.path(), "other", "1.0.0", &[("b", "<1.5")]);
+        let published: &[Published] = &[("a", "1.9.0", &[]), ("b", "1.5.0", &[])];
+
+        let resolution = resolve_with(store.path(), &[("a", "^1")], published).unwrap();
+        assert!(matches!(resolution.nodes["a"].origin, Origin::Installed));
+        assert_eq!(resolution.nodes["a"].version, Some(Version::new(1, 1, 0)));
+
+        let error = resolve_with(store.path(), &[("b", "^1")], published).err().unwrap();
+        assert_eq!(error, "Version conflict:\n  'other' (installed) needs b <1.5, but 1.5.0 would be installed");
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt;
+use crate::lexer::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found while parsing or running a template, pointing at the
+/// line and column it refers to when there is one.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub template: String,
+    pub span: Option<Span>,
+    pub snippet: Option<String>,
+}
+
+/// A template's source text, used to attach snippets to diagnostics.
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: &str, text: String) -> Self {
+        Source { name: name.to_string(), text }
+    }
+
+    pub fn diagnostic(&self, severity: Severity, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            template: self.name.clone(),
+            span: Some(span),
+            snippet: self.text.lines().nth(span.line - 1).map(str::to_string),
+        }
+    }
+
+    pub fn error(&self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.diagnostic(Severity::Error, span, message)
+    }
+}
+
+impl Diagnostic {
+    /// An error about a template as a whole, e.g. one that cannot be read.
+    pub fn template_error(template: &str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            template: template.to_string(),
+            span: None,
+            snippet: None,
+        }
+    }
+
+    pub fn template_warning(template: &str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::template_error(template, message)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (label, color) = match self.severity {
+            Severity::Error => ("error", "\x1b[1;31m"),
+            Severity::Warning => ("warning", "\x1b[1;33m"),
+        };
+        writeln!(f, "{}{}\x1b[0m\x1b[1m: {}\x1b[0m", color, label, self.message)?;
+
+        let Some(span) = self.span else {
+            return writeln!(f, " \x1b[1;34m-->\x1b[0m {}", self.template);
+        };
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        writeln!(f, "{} \x1b[1;34m-->\x1b[0m {}:{}:{}", gutter, self.template, span.line, span.column)?;
+
+        if let Some(snippet) = &self.snippet {
+            // Columns count bytes, so the caret is indented by the characters
+            // that start before it.
+            let offset = span.column.saturating_sub(1);
+            let caret_offset: String = snippet
+                .char_indices()
+                .take_while(|&(i, _)| i < offset)
+                .map(|(_, c)| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            writeln!(f, "{} \x1b[1;34m|\x1b[0m", gutter)?;
+            writeln!(f, "\x1b[1;34m{} |\x1b[0m {}", span.line, snippet)?;
+            writeln!(f, "{} \x1b[1;34m|\x1b[0m {}{}^\x1b[0m", gutter, caret_offset, color)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Every diagnostic produced for one template run.
+#[derive(Debug, Default)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.0.iter().filter(|d| d.severity == Severity::Error).count()
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Diagnostics(vec![diagnostic])
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.0 {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+/// A template run that failed. The diagnostics may point into templates it
+/// includes or imports, but the run is summed up under the template that
+/// was run.
+#[derive(Debug)]
+pub struct Failure {
+    pub template: String,
+    pub diagnostics: Diagnostics,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.diagnostics)?;
+
+        let errors = self.diagnostics.error_count();
+        if errors > 0 {
+            write!(
+                f,
+                "\x1b[1;31merror\x1b[0m\x1b[1m: template '{}' failed with {} error{}\x1b[0m",
+                self.template,
+                errors,
+                if errors == 1 { "" } else { "s" }
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failure {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_counts_characters_not_bytes() {
+        let source = Source::new("t", "mkdir: é/$x".to_string());
+        // `$` is the ninth character, but starts at byte 10.
+        let rendered = source.error(Span { line: 1, column: 11 }, "Undefined variable '$x'").to_string();
+        let caret = rendered.lines().last().unwrap();
+        assert!(caret.ends_with(&format!("|\x1b[0m {}\x1b[1;31m^\x1b[0m", " ".repeat(9))), "{:?}", caret);
+    }
+
+    #[test]
+    fn failure_is_summed_up_under_the_template_that_was_run() {
+        let included = Source::new("base", "mkdir: $x".to_string());
+        let failure = Failure {
+            template: "api".to_string(),
+            diagnostics: included.error(Span { line: 1, column: 8 }, "Undefined variable '$x'").into(),
+        };
+        let rendered = failure.to_string();
+        assert!(rendered.contains("\x1b[0m base:1:8"), "{}", rendered);
+        assert!(rendered.ends_with("template 'api' failed with 1 error\x1b[0m"), "{}", rendered);
+    }
+}
@@ -0,0 +1,251 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use crate::iostream;
+use crate::journal::Journal;
+
+/// Performs the side effects of a template. Paths are passed as written in the
+/// template (after variable substitution) and are relative to the current
+/// directory of the run.
+pub trait Executor {
+    fn mkdir(&mut self, path: &str) -> Result<(), String>;
+    fn create_file(&mut self, path: &str) -> Result<(), String>;
+    fn write_file(&mut self, path: &str, content: &str) -> Result<(), String>;
+    fn cd(&mut self, dir: &str) -> Result<(), String>;
+    /// Copies the file at `from`, outside of the run, to `to` byte for byte.
+    fn copy_file(&mut self, from: &Path, to: &str) -> Result<(), String>;
+    fn run_command(&mut self, command: &str) -> Result<(), String>;
+    /// Where `path` points for this run, used to inspect the filesystem.
+    fn resolve(&self, path: &str) -> PathBuf;
+}
+
+/// Applies every effect to the real filesystem, journaling created and
+/// overwritten paths when a journal is attached.
+pub struct FsExecutor {
+    pub journal: Option<Journal>,
+}
+
+impl FsExecutor {
+    fn journal_dir(&mut self, path: &str) -> Result<(), String> {
+        match &mut self.journal {
+            Some(journal) => journal
+                .before_create_dir(Path::new(path))
+                .map_err(|e| format!("Failed to record directory '{}' for rollback: {}", path, e)),
+            None => Ok(()),
+        }
+    }
+
+    fn journal_file(&mut self, path: &str) -> Result<(), String> {
+        match &mut self.journal {
+            Some(journal) => journal
+                .before_write_file(Path::new(path))
+                .map_err(|e| format!("Failed to back up '{}' for rollback: {}", path, e)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Executor for FsExecutor {
+    fn resolve(&self, path: &str) -> PathBuf {
+        PathBuf::from(path)
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<(), String> {
+        self.journal_dir(path)?;
+        with_loader("Creating directory \x1b[90m...\x1b[0m".to_string(), || fs::create_dir_all(path))
+            .map_err(|e| format!("Failed to create directory '{}': {}", path, e))
+    }
+
+    fn create_file(&mut self, path: &str) -> Result<(), String> {
+        self.journal_file(path)?;
+        with_loader("Creating file \x1b[90m...\x1b[0m".to_string(), || File::create(path))
+            .map(|_| ())
+            .map_err(|e| format!("Failed to create file '{}': {}", path, e))
+    }
+
+    fn write_file(&mut self, path: &str, content: &str) -> Result<(), String> {
+        self.journal_file(path)?;
+        with_loader("Writing file \x1b[90m...\x1b[0m".to_string(), || -> std::io::Result<()> {
+            let mut file = File::create(path)?;
+            file.write_all(content.as_bytes())?;
+            Ok(())
+        })
+        .map_err(|e| format!("Failed to write to file '{}': {}", path, e))
+    }
+
+    fn copy_file(&mut self, from: &Path, to: &str) -> Result<(), String> {
+        self.journal_file(to)?;
+        with_loader("Copying file \x1b[90m...\x1b[0m".to_string(), || fs::copy(from, to))
+            .map(|_| ())
+            .map_err(|e| format!("Failed to copy '{}' to '{}': {}", from.display(), to, e))
+    }
+
+    fn cd(&mut self, dir: &str) -> Result<(), String> {
+        with_loader(format!("Changing directory to '{}'", dir), || env::set_current_dir(dir))
+            .map_err(|e| format!("Failed to change directory to '{}': {}", dir, e))
+    }
+
+    fn run_command(&mut self, command: &str) -> Result<(), String> {
+        if command.trim().is_empty() {
+            return Err("Empty command".to_string());
+        }
+        let result = with_loader(format!("Running command {} \x1b[90m...\x1b[0m", command), || {
+            #[cfg(target_os = "windows")]
+            {
+                Command::new("cmd")
+                    .current_dir(std::env::current_dir().unwrap())
+                    .args(["/C", command])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+            }
+
+            #[cfg(not(target_os = "windows"))]
+            {
+                let args: Vec<&str> = command.split_whitespace().collect();
+                Command::new(args[0])
+                    .current_dir(std::env::current_dir().unwrap())
+                    .args(&args[1..])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+            }
+        });
+
+        match result {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("Command '{}' failed with exit code {:?}", command, status.code())),
+            Err(e) => Err(format!("Failed to execute command '{}': {}", command, e)),
+        }
+    }
+}
+
+pub enum Action {
+    Mkdir(PathBuf),
+    CreateFile(PathBuf),
+    WriteFile { path: PathBuf, bytes: usize },
+    CopyFile { from: PathBuf, to: PathBuf },
+    Cd(PathBuf),
+    Command { command: String, dir: PathBuf },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Mkdir(path) => write!(f, "mkdir    {}", path.display()),
+            Action::CreateFile(path) => write!(f, "create   {}", path.display()),
+            Action::WriteFile { path, bytes } => write!(f, "write    {} ({} bytes)", path.display(), bytes),
+            Action::CopyFile { from, to } => write!(f, "copy     {} (from {})", to.display(), from.display()),
+            Action::Cd(path) => write!(f, "cd       {}", path.display()),
+            Action::Command { command, dir } => write!(f, "command  {} (in {})", command, dir.display()),
+        }
+    }
+}
+
+/// Records effects instead of applying them, tracking the working directory
+/// virtually so every planned action carries its resolved absolute path.
+pub struct PlanExecutor {
+    cwd: PathBuf,
+    pub actions: Vec<Action>,
+}
+
+impl PlanExecutor {
+    pub fn new(cwd: PathBuf) -> Self {
+        PlanExecutor { cwd, actions: Vec::new() }
+    }
+
+    fn normalize(&self, path: &str) -> PathBuf {
+        let mut resolved = PathBuf::new();
+        for component in self.cwd.join(path).components() {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::CurDir => {}
+                other => resolved.push(other),
+            }
+        }
+        resolved
+    }
+
+    pub fn print(&self) {
+        println!("Planned actions (dry run, nothing was changed):");
+        if self.actions.is_empty() {
+            println!("  (none)");
+        }
+        for (i, action) in self.actions.iter().enumerate() {
+            println!("  {:>3}. {}", i + 1, action);
+        }
+    }
+}
+
+impl Executor for PlanExecutor {
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.normalize(path)
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<(), String> {
+        self.actions.push(Action::Mkdir(self.resolve(path)));
+        Ok(())
+    }
+
+    fn create_file(&mut self, path: &str) -> Result<(), String> {
+        self.actions.push(Action::CreateFile(self.resolve(path)));
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &str, content: &str) -> Result<(), String> {
+        self.actions.push(Action::WriteFile {
+            path: self.resolve(path),
+            bytes: content.len(),
+        });
+        Ok(())
+    }
+
+    fn copy_file(&mut self, from: &Path, to: &str) -> Result<(), String> {
+        self.actions.push(Action::CopyFile {
+            from: from.to_path_buf(),
+            to: self.resolve(to),
+        });
+        Ok(())
+    }
+
+    fn cd(&mut self, dir: &str) -> Result<(), String> {
+        self.cwd = self.resolve(dir);
+        self.actions.push(Action::Cd(self.cwd.clone()));
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: &str) -> Result<(), String> {
+        self.actions.push(Action::Command {
+            command: command.to_string(),
+            dir: self.cwd.clone(),
+        });
+        Ok(())
+    }
+}
+
+/// Runs `action` while a spinner with `message` is shown.
+fn with_loader<T>(message: String, action: impl FnOnce() -> T) -> T {
+    let running = Arc::new(AtomicBool::new(true));
+    let loader_flag = running.clone();
+
+    let loader = thread::spawn(move || {
+        iostream::show_loader(&message, loader_flag);
+    });
+
+    let result = action();
+
+    running.store(false, Ordering::Relaxed);
+    loader.join().unwrap();
+
+    result
+}
@@ -0,0 +1,562 @@
+use crate::ast::{BinaryOp, Expr, Filter};
+use crate::filter::FILTERS;
+use crate::lexer::is_identifier;
+use crate::value::Value;
+
+/// A syntax error in an expression, at a byte offset into its text.
+pub struct ExprError {
+    pub message: String,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Var(String),
+    Str(String),
+    Literal(String),
+    Word(String),
+    Op(BinaryOp),
+    Not,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Colon,
+    Comma,
+    /// A single `|`, which passes a value on to a filter.
+    Pipe,
+    /// `.name` right after a value, as in `$cfg.port`.
+    Field(String),
+}
+
+/// Built-in functions callable in expressions, with their number of arguments.
+pub const FUNCTIONS: &[(&str, usize)] = &[
+    ("exists", 1),
+    ("is_dir", 1),
+    ("env", 1),
+    ("len", 1),
+];
+
+/// Characters that end a bare word.
+const DELIMITERS: &[char] = &['(', ')', '[', ']', '{', '}', '!', '=', '<', '>', '&', '|', ',', '"', '\'', '$'];
+
+fn tokenize(text: &str) -> Result<Vec<(Tok, usize)>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    // Inside `{ ... }` a `:` separates keys from values; elsewhere it is part
+    // of a word, as in `http://`.
+    let mut brace_depth = 0usize;
+    let mut after_space = false;
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            after_space = true;
+            continue;
+        }
+
+        let follows_value = !after_space
+            && tokens.last().is_some_and(|(token, _)| matches!(token, Tok::Var(_) | Tok::RBracket | Tok::Field(_)));
+        after_space = false;
+        if c == '.' && follows_value {
+            chars.next();
+            let name = take_name(&mut chars);
+            if name.is_empty() {
+                return Err(ExprError {
+                    message: "Expected a key name after '.'".to_string(),
+                    offset: start,
+                });
+            }
+            tokens.push((Tok::Field(name), start));
+            continue;
+        }
+
+        let two: String = text[start..].chars().take(2).collect();
+        let op = match two.as_str() {
+            "||" => Some(BinaryOp::Or),
+            "&&" => Some(BinaryOp::And),
+            "==" => Some(BinaryOp::Eq),
+            "!=" => Some(BinaryOp::Ne),
+            "<=" => Some(BinaryOp::Le),
+            ">=" => Some(BinaryOp::Ge),
+            "=~" => Some(BinaryOp::Matches),
+            _ => None,
+        };
+        if let Some(op) = op {
+            chars.next();
+            chars.next();
+            tokens.push((Tok::Op(op), start));
+            continue;
+        }
+
+        let single = match c {
+            '<' => Some(Tok::Op(BinaryOp::Lt)),
+            '>' => Some(Tok::Op(BinaryOp::Gt)),
+            '!' => Some(Tok::Not),
+            '(' => Some(Tok::LParen),
+            ')' => Some(Tok::RParen),
+            '[' => Some(Tok::LBracket),
+            ']' => Some(Tok::RBracket),
+            '{' => {
+                brace_depth += 1;
+                Some(Tok::LBrace)
+            }
+            '}' => {
+                brace_depth = brace_depth.saturating_sub(1);
+                Some(Tok::RBrace)
+            }
+            ':' if brace_depth > 0 => Some(Tok::Colon),
+            ',' => Some(Tok::Comma),
+            _ => None,
+        };
+        if let Some(token) = single {
+            chars.next();
+            tokens.push((token, start));
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                let mut closed = false;
+                while let Some((_, ch)) = chars.next() {
+                    match ch {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                value.push(escaped);
+                            }
+                        }
+                        _ if ch == c => {
+                            closed = true;
+                            break;
+                        }
+                        _ => value.push(ch),
+                    }
+                }
+                if !closed {
+                    return Err(ExprError {
+                        message: format!("Unterminated string starting with {}", c),
+                        offset: start,
+                    });
+                }
+                tokens.push((if c == '"' { Tok::Str(value) } else { Tok::Literal(value) }, start));
+            }
+            '$' => {
+                chars.next();
+                let name = take_name(&mut chars);
+                if name.is_empty() {
+                    return Err(ExprError {
+                        message: "Expected a variable name after '$'".to_string(),
+                        offset: start,
+                    });
+                }
+                tokens.push((Tok::Var(name), start));
+            }
+            '|' => {
+                chars.next();
+                tokens.push((Tok::Pipe, start));
+            }
+            '=' | '&' => {
+                return Err(ExprError {
+                    message: format!("Unknown operator '{}', did you mean '{}{}'?", c, c, c),
+                    offset: start,
+                });
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&(_, ch)) = chars.peek() {
+                    if ch.is_whitespace() || DELIMITERS.contains(&ch) || (ch == ':' && brace_depth > 0) {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push((Tok::Word(word), start));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Takes a variable or key name: a letter or `_`, then letters, digits and `_`.
+fn take_name(chars: &mut std::iter::Peekable<std::str::CharIndices>) -> String {
+    let mut name = String::new();
+    while let Some(&(_, ch)) = chars.peek() {
+        let allowed = ch.is_alphabetic() || ch == '_' || (!name.is_empty() && ch.is_alphanumeric());
+        if !allowed {
+            break;
+        }
+        name.push(ch);
+        chars.next();
+    }
+    name
+}
+
+/// Reads the right-hand side of `var:` as a typed value when it is one: a
+/// list, a map, a number, a boolean or an expression starting with a
+/// variable such as `$deps[0]`. Anything else is `None` and taken as text.
+pub fn parse_value(text: &str) -> Option<Expr> {
+    let text = text.trim();
+    let typed = text.starts_with(['[', '{', '$'])
+        || text.starts_with("len(")
+        || !matches!(Value::from_word(text), Value::Str(_));
+
+    if typed {
+        parse(text).ok()
+    } else {
+        None
+    }
+}
+
+/// Parses an expression such as `$env == "prod" && !exists(.git)`.
+///
+/// From loosest to tightest binding: `||`, `&&`, comparisons (`==`, `!=`,
+/// `<`, `>`, `<=`, `>=`, `=~`, `contains`, `starts_with`, `ends_with`), then
+/// unary `!`. Comparisons don't chain; use parentheses to group.
+pub fn parse(text: &str) -> Result<Expr, ExprError> {
+    let mut parser = ExprParser::new(text, false)?;
+    let expr = parser.parse_or()?;
+
+    if parser.peek() == Some(&Tok::Pipe) {
+        return Err(parser.error("Unknown operator '|', did you mean '||'? Filters only work inside '${...}'"));
+    }
+    parser.finish()?;
+    Ok(expr)
+}
+
+/// Parses the inside of `${...}`: an expression in which a bare name such as
+/// `name` in `${name}` or `${deps[0]}` refers to a variable, followed by any
+/// number of `| filter` or `| filter(args)`.
+pub fn parse_embedded(text: &str) -> Result<(Expr, Vec<Filter>), ExprError> {
+    let mut parser = ExprParser::new(text, true)?;
+    let expr = parser.parse_or()?;
+
+    let mut filters = Vec::new();
+    while parser.peek() == Some(&Tok::Pipe) {
+        parser.pos += 1;
+        filters.push(parser.parse_filter()?);
+    }
+    parser.finish()?;
+    Ok((expr, filters))
+}
+
+struct ExprParser {
+    tokens: Vec<(Tok, usize)>,
+    pos: usize,
+    end: usize,
+    bare_vars: bool,
+}
+
+impl ExprParser {
+    fn new(text: &str, bare_vars: bool) -> Result<Self, ExprError> {
+        Ok(ExprParser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            end: text.len(),
+            bare_vars,
+        })
+    }
+
+    fn finish(&self) -> Result<(), ExprError> {
+        match self.tokens.get(self.pos) {
+            Some(_) => Err(self.error("Unexpected input after expression")),
+            None => Ok(()),
+        }
+    }
+
+    fn parse_filter(&mut self) -> Result<Filter, ExprError> {
+        let offset = self.offset();
+        let Some(Tok::Word(name)) = self.peek().cloned() else {
+            return Err(self.error("Expected a filter name after '|'"));
+        };
+        let Some(&(_, min, max)) = FILTERS.iter().find(|(filter, _, _)| *filter == name) else {
+            return Err(self.error(&format!("Unknown filter '{}'", name)));
+        };
+        self.pos += 1;
+
+        let args = if self.peek() == Some(&Tok::LParen) {
+            self.pos += 1;
+            self.parse_list(Tok::RParen, "')'", |parser| parser.parse_or())?
+        } else {
+            Vec::new()
+        };
+
+        if args.len() < min || args.len() > max {
+            let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+            return Err(ExprError {
+                message: format!("Filter '{}' expects {} argument(s), but {} were provided", name, expected, args.len()),
+                offset,
+            });
+        }
+        Ok(Filter { name, args })
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(_, o)| *o)
+    }
+
+    fn error(&self, message: &str) -> ExprError {
+        ExprError {
+            message: message.to_string(),
+            offset: self.offset(),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ExprError> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Tok::Op(BinaryOp::Or)) {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ExprError> {
+        let mut left = self.parse_comparison()?;
+        while self.peek() == Some(&Tok::Op(BinaryOp::And)) {
+            self.pos += 1;
+            let right = self.parse_comparison()?;
+            left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn comparison_op(&self) -> Option<BinaryOp> {
+        match self.peek()? {
+            Tok::Op(op) if !matches!(op, BinaryOp::Or | BinaryOp::And) => Some(*op),
+            Tok::Word(word) => match word.as_str() {
+                "contains" => Some(BinaryOp::Contains),
+                "starts_with" => Some(BinaryOp::StartsWith),
+                "ends_with" => Some(BinaryOp::EndsWith),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ExprError> {
+        let left = self.parse_unary()?;
+        let Some(op) = self.comparison_op() else {
+            return Ok(left);
+        };
+        self.pos += 1;
+        let right = self.parse_unary()?;
+
+        if self.comparison_op().is_some() {
+            return Err(self.error("Comparisons can't be chained, use parentheses"));
+        }
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ExprError> {
+        if self.peek() == Some(&Tok::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_postfix()
+    }
+
+    /// Parses comma separated items up to and including `close`.
+    fn parse_list<T>(
+        &mut self,
+        close: Tok,
+        expected: &str,
+        mut item: impl FnMut(&mut Self) -> Result<T, ExprError>,
+    ) -> Result<Vec<T>, ExprError> {
+        let mut items = Vec::new();
+        while self.peek() != Some(&close) {
+            items.push(item(self)?);
+            match self.peek() {
+                Some(Tok::Comma) => self.pos += 1,
+                Some(token) if *token == close => {}
+                _ => return Err(self.error(&format!("Expected ',' or {}", expected))),
+            }
+        }
+        self.pos += 1;
+        Ok(items)
+    }
+
+    /// A value followed by any number of `[index]` and `.key` accessors.
+    fn parse_postfix(&mut self) -> Result<Expr, ExprError> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            match self.peek() {
+                Some(Tok::LBracket) => {
+                    self.pos += 1;
+                    let index = self.parse_or()?;
+                    if self.peek() != Some(&Tok::RBracket) {
+                        return Err(self.error("Expected ']'"));
+                    }
+                    self.pos += 1;
+                    expr = Expr::Index(Box::new(expr), Box::new(index));
+                }
+                Some(Tok::Field(name)) => {
+                    let name = name.clone();
+                    self.pos += 1;
+                    expr = Expr::Field(Box::new(expr), name);
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ExprError> {
+        let Some((token, _)) = self.tokens.get(self.pos).cloned() else {
+            return Err(self.error("Expected a value"));
+        };
+        self.pos += 1;
+
+        match token {
+            Tok::Var(name) => Ok(Expr::Var(name)),
+            Tok::Str(value) => Ok(Expr::Str(value)),
+            Tok::Literal(value) => Ok(Expr::Literal(Value::Str(value))),
+            Tok::LBracket => {
+                let items = self.parse_list(Tok::RBracket, "']'", |parser| parser.parse_or())?;
+                Ok(Expr::List(items))
+            }
+            Tok::LBrace => {
+                let entries = self.parse_list(Tok::RBrace, "'}'", |parser| {
+                    let key = match parser.tokens.get(parser.pos).map(|(t, _)| t.clone()) {
+                        Some(Tok::Word(key) | Tok::Str(key) | Tok::Literal(key)) => key,
+                        _ => return Err(parser.error("Expected a key")),
+                    };
+                    parser.pos += 1;
+                    if parser.peek() != Some(&Tok::Colon) {
+                        return Err(parser.error("Expected ':' after the key"));
+                    }
+                    parser.pos += 1;
+                    Ok((key, parser.parse_or()?))
+                })?;
+                Ok(Expr::Map(entries))
+            }
+            Tok::Word(word) if self.peek() == Some(&Tok::LParen) => {
+                let name_offset = self.tokens[self.pos - 1].1;
+                let Some(&(_, arity)) = FUNCTIONS.iter().find(|(name, _)| *name == word) else {
+                    return Err(ExprError {
+                        message: format!("Unknown function '{}'", word),
+                        offset: name_offset,
+                    });
+                };
+                self.pos += 1;
+                let mut args = Vec::new();
+                if self.peek() != Some(&Tok::RParen) {
+                    loop {
+                        args.push(self.parse_or()?);
+                        if self.peek() != Some(&Tok::Comma) {
+                            break;
+                        }
+                        self.pos += 1;
+                    }
+                }
+                if self.peek() != Some(&Tok::RParen) {
+                    return Err(self.error("Expected ')' after arguments"));
+                }
+                self.pos += 1;
+
+                if args.len() != arity {
+                    return Err(ExprError {
+                        message: format!("'{}' expects {} argument(s), but {} were provided", word, arity, args.len()),
+                        offset: name_offset,
+                    });
+                }
+                Ok(Expr::Call(word, args))
+            }
+            Tok::Word(word) => match Value::from_word(&word) {
+                // `cfg.port` is a single word, since a `.` only starts a key
+                // right after a `$name`.
+                Value::Str(_) if self.bare_vars && word.split('.').all(is_identifier) => {
+                    let mut names = word.split('.');
+                    let var = Expr::Var(names.next().unwrap_or_default().to_string());
+                    Ok(names.fold(var, |expr, name| Expr::Field(Box::new(expr), name.to_string())))
+                }
+                value => Ok(Expr::Literal(value)),
+            },
+            Tok::LParen => {
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&Tok::RParen) {
+                    return Err(self.error("Expected ')'"));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.error("Expected a value"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `expr` back out with every binary operation in parentheses.
+    fn show(expr: &Expr) -> String {
+        match expr {
+            Expr::Var(name) => format!("${}", name),
+            Expr::Str(text) => format!("{:?}", text),
+            Expr::Literal(value) => value.to_string(),
+            Expr::Not(inner) => format!("!{}", show(inner)),
+            Expr::Binary(op, left, right) => format!("({} {:?} {})", show(left), op, show(right)),
+            Expr::Call(name, args) => format!("{}({})", name, args.iter().map(show).collect::<Vec<_>>().join(", ")),
+            Expr::List(items) => format!("[{}]", items.iter().map(show).collect::<Vec<_>>().join(", ")),
+            Expr::Map(entries) => format!("{{{} entries}}", entries.len()),
+            Expr::Index(value, index) => format!("{}[{}]", show(value), show(index)),
+            Expr::Field(value, name) => format!("{}.{}", show(value), name),
+        }
+    }
+
+    fn parsed(text: &str) -> String {
+        match parse(text) {
+            Ok(expr) => show(&expr),
+            Err(e) => panic!("'{}' should parse: {}", text, e.message),
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(parsed("$a || $b && $c"), "($a Or ($b And $c))");
+        assert_eq!(parsed("$a && $b || $c"), "(($a And $b) Or $c)");
+        assert_eq!(parsed("($a || $b) && $c"), "(($a Or $b) And $c)");
+    }
+
+    #[test]
+    fn not_binds_tightest() {
+        assert_eq!(parsed("!$a && $b"), "(!$a And $b)");
+        assert_eq!(parsed("!$a == b"), "(!$a Eq b)");
+        assert_eq!(parsed("!($a || $b)"), "!($a Or $b)");
+        assert_eq!(parsed("!!$a"), "!!$a");
+    }
+
+    #[test]
+    fn comparisons_bind_tighter_than_logic_and_do_not_chain() {
+        assert_eq!(
+            parsed("$env == prod && $port >= 1024 || exists(.git)"),
+            "((($env Eq prod) And ($port Ge 1024)) Or exists(.git))"
+        );
+        let Err(error) = parse("$a == b == c") else {
+            panic!("chained comparisons should not parse");
+        };
+        assert!(error.message.contains("can't be chained"), "{}", error.message);
+    }
+
+    #[test]
+    fn embedded_expressions_read_bare_names_as_variables() {
+        let Ok((expr, filters)) = parse_embedded("cfg.name | kebab | default(\"app\")") else {
+            panic!("should parse");
+        };
+        assert_eq!(show(&expr), "$cfg.name");
+        let names: Vec<&str> = filters.iter().map(|filter| filter.name.as_str()).collect();
+        assert_eq!(names, ["kebab", "default"]);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::value::Value;
+
+/// Filters usable in `${value | filter}`, with the least and most arguments
+/// they take.
+pub const FILTERS: &[(&str, usize, usize)] = &[
+    ("pascal", 0, 0),
+    ("camel", 0, 0),
+    ("kebab", 0, 0),
+    ("snake", 0, 0),
+    ("upper", 0, 0),
+    ("lower", 0, 0),
+    ("trim", 0, 0),
+    ("replace", 2, 2),
+    ("default", 1, 1),
+    ("pluralize", 0, 0),
+];
+
+/// Plural forms that don't follow the suffix rules in `pluralize`.
+const IRREGULAR_PLURALS: &[(&str, &str)] = &[
+    ("child", "children"),
+    ("person", "people"),
+    ("man", "men"),
+    ("woman", "women"),
+    ("mouse", "mice"),
+    ("goose", "geese"),
+    ("foot", "feet"),
+    ("tooth", "teeth"),
+];
+
+/// Applies the filter `name` to `value`. Argument counts have already been
+/// checked when the template was parsed.
+pub fn apply(name: &str, value: Value, args: &[Value]) -> Result<Value, String> {
+    let text = value.to_string();
+
+    let result = match name {
+        "pascal" => words(&text).iter().map(|word| capitalize(word)).collect(),
+        "camel" => words(&text)
+            .iter()
+            .enumerate()
+            .map(|(i, word)| if i == 0 { word.to_lowercase() } else { capitalize(word) })
+            .collect(),
+        "kebab" => join_lowercase(&text, "-"),
+        "snake" => join_lowercase(&text, "_"),
+        "upper" => text.to_uppercase(),
+        "lower" => text.to_lowercase(),
+        "trim" => text.trim().to_string(),
+        "replace" => text.replace(&args[0].to_string(), &args[1].to_string()),
+        "default" if text.is_empty() => return Ok(args[0].clone()),
+        "default" => return Ok(value),
+        "pluralize" => pluralize(&text),
+        _ => return Err(format!("Unknown filter '{}'", name)),
+    };
+
+    Ok(Value::Str(result))
+}
+
+/// Splits an identifier in any common casing into its words, so
+/// `myComponent`, `my-component`, `My Component` and `MY_COMPONENT` all
+/// become `my` and `component`. Runs of capitals count as one word:
+/// `HTMLParser` is `HTML` and `Parser`.
+fn words(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut words = Vec::new();
+    let mut current = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+
+        if let Some(prev) = current.chars().last() {
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            let starts_word = c.is_uppercase()
+                && (prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_is_lower));
+            if starts_word {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(c);
+    }
+
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+        None => String::new(),
+    }
+}
+
+fn join_lowercase(text: &str, separator: &str) -> String {
+    words(text)
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Pluralizes the last word of `text` with the common English rules:
+/// `box` to `boxes`, `category` to `categories`, `person` to `people`,
+/// anything else gets an `s`.
+fn pluralize(text: &str) -> String {
+    if text.is_empty() {
+        return String::new();
+    }
+
+    for (singular, plural) in IRREGULAR_PLURALS {
+        let Some(start) = text.len().checked_sub(singular.len()) else {
+            continue;
+        };
+        if !text.is_char_boundary(start) || !text[start..].eq_ignore_ascii_case(singular) {
+            continue;
+        }
+
+        // Only whole words, so `human` doesn't become `humen`, but the last
+        // part of `SalesPerson` counts.
+        let capitalized = text[start..].starts_with(char::is_uppercase);
+        let whole_word = text[..start].chars().last().is_none_or(|c| !c.is_alphabetic() || capitalized);
+        if whole_word {
+            let plural = if text[start..].chars().all(char::is_uppercase) {
+                plural.to_uppercase()
+            } else if capitalized {
+                capitalize(plural)
+            } else {
+                plural.to_string()
+            };
+            return format!("{}{}", &text[..start], plural);
+        }
+    }
+
+    let lower = text.to_lowercase();
+    let upper = text.chars().all(|c| !c.is_lowercase());
+    let (stem, suffix) = if ["s", "x", "z", "ch", "sh"].iter().any(|end| lower.ends_with(end)) {
+        (text, "es")
+    } else if lower.ends_with('y') && !lower[..lower.len() - 1].ends_with(['a', 'e', 'i', 'o', 'u']) {
+        (&text[..text.len() - 1], "ies")
+    } else {
+        (text, "s")
+    };
+
+    if upper {
+        format!("{}{}", stem, suffix.to_uppercase())
+    } else {
+        format!("{}{}", stem, suffix)
+    }
+}
@@ -0,0 +1,878 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Component, Path};
+use crate::answers::Answers;
+use crate::ast::{BinaryOp, Block, Branch, CommandLine, Content, Expr, Filter, FunctionDefinition, Iterable, Prompt, PromptKind, Stmt, StmtKind, VarValue};
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::loader::{Template, Templates};
+use crate::executor::Executor;
+use crate::expr;
+use crate::filter;
+use crate::parse::FILES_DIR;
+use crate::prompt;
+use crate::value::Value;
+use regex::Regex;
+
+/// How often a `while:` loop may run before it is treated as endless.
+const MAX_WHILE_ITERATIONS: usize = 1000;
+
+type Variables = HashMap<String, Value>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop at the first failing step.
+    Abort,
+    /// Report failing steps and carry on with the rest of the template.
+    KeepGoing,
+}
+
+pub struct RunOptions {
+    pub dry_run: bool,
+    pub error_policy: ErrorPolicy,
+    /// Journal created and overwritten paths so a failed run can be undone.
+    pub rollback: bool,
+    pub answers: Answers,
+}
+
+/// Runs a parsed template and returns the values given to its `input()`
+/// prompts. Depending on the error policy, execution stops at the first
+/// failing step or continues past it; either way every failure is returned
+/// once the run is over.
+pub fn run(
+    templates: &Templates,
+    executor: &mut dyn Executor,
+    options: &RunOptions,
+) -> Result<BTreeMap<String, String>, Diagnostics> {
+    let mut interpreter = Interpreter {
+        templates,
+        template: &templates.main,
+        executor,
+        answers: &options.answers,
+        error_policy: options.error_policy,
+        try_depth: 0,
+        inputs: BTreeMap::new(),
+        diagnostics: Diagnostics::default(),
+    };
+    let mut variables = Variables::new();
+
+    let result = interpreter
+        .ask_parameters(&mut variables)
+        .and_then(|()| interpreter.execute_block(&templates.main.program.body, &mut variables));
+    if let Err(diagnostic) = result {
+        interpreter.diagnostics.push(diagnostic);
+    }
+
+    if interpreter.diagnostics.is_empty() {
+        Ok(interpreter.inputs)
+    } else {
+        Err(interpreter.diagnostics)
+    }
+}
+
+struct Interpreter<'a> {
+    templates: &'a Templates,
+    /// The template the running code comes from. Included templates and
+    /// imported functions run in their own, so their errors point into their
+    /// source and their calls and `files/` resolve there.
+    template: &'a Template,
+    executor: &'a mut dyn Executor,
+    answers: &'a Answers,
+    error_policy: ErrorPolicy,
+    /// Number of enclosing `try` blocks; failures inside one always unwind to it.
+    try_depth: usize,
+    inputs: BTreeMap<String, String>,
+    diagnostics: Diagnostics,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Decides what a failed step means for the run: with `--keep-going`
+    /// outside of `try` it is recorded and execution continues, otherwise it
+    /// is returned so execution stops.
+    fn fail(&mut self, diagnostic: Diagnostic) -> Result<(), Diagnostic> {
+        if self.error_policy == ErrorPolicy::KeepGoing && self.try_depth == 0 {
+            self.diagnostics.push(diagnostic);
+            Ok(())
+        } else {
+            Err(diagnostic)
+        }
+    }
+
+    /// Asks for the parameters declared in the running template's manifest.
+    /// Those that are already set, by the template including this one, are
+    /// skipped.
+    fn ask_parameters(&mut self, variables: &mut Variables) -> Result<(), Diagnostic> {
+        let template = self.template;
+        for parameter in &template.program.parameters {
+            if variables.contains_key(&parameter.name) {
+                continue;
+            }
+            match self.prompt(&parameter.name, &parameter.prompt, variables) {
+                Ok(Some(value)) => {
+                    variables.insert(parameter.name.clone(), value);
+                }
+                Ok(None) => {}
+                Err(e) => return Err(Diagnostic::template_error(&template.source.name, e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_block(&mut self, block: &Block, variables: &mut Variables) -> Result<(), Diagnostic> {
+        for statement in block {
+            if let Err(diagnostic) = self.execute_statement(statement, variables) {
+                self.fail(diagnostic)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_statement(&mut self, statement: &Stmt, variables: &mut Variables) -> Result<(), Diagnostic> {
+        let result = match &statement.kind {
+            StmtKind::Var { name, value } => self.handle_var(name, value, variables),
+            StmtKind::Mkdir(name) => self.handle_mkdir(name, variables),
+            StmtKind::CreateFile(name) => self.handle_create_file(name, variables),
+            StmtKind::WriteFile { path, content } => self.handle_write_file(path, content, variables),
+            StmtKind::Cd(dir) => self.handle_cd(dir, variables),
+            StmtKind::Tree { source, target, render } => self.handle_tree(source, target, *render, variables),
+            StmtKind::Command(lines) => return self.handle_command(lines, variables),
+            StmtKind::If { branches, otherwise } => return self.handle_if(branches, otherwise.as_ref(), variables),
+            StmtKind::For { var, iterable, body } => return self.handle_for(statement, var, iterable, body, variables),
+            StmtKind::While { condition, body } => return self.handle_while(statement, condition, body, variables),
+            StmtKind::Call { name, args } => return self.handle_function_call(statement, name, args, variables),
+            StmtKind::Try { body, catch } => return self.handle_try(body, catch.as_ref(), variables),
+            StmtKind::Include(name) => return self.handle_include(statement, name, variables),
+        };
+
+        result.map_err(|e| self.template.source.error(statement.span, e))
+    }
+
+    fn handle_var(&mut self, name: &str, value: &VarValue, variables: &mut Variables) -> Result<(), String> {
+        let value = match value {
+            VarValue::Prompt(prompt) => match self.prompt(name, prompt, variables)? {
+                Some(value) => value,
+                None => return Ok(()),
+            },
+            VarValue::Text(raw) => self.value_of(raw, variables)?,
+        };
+
+        variables.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Gets the value for a prompt from the supplied answers, the default in
+    /// non-interactive mode, or by asking. Everything but passwords is
+    /// remembered for the answers record.
+    ///
+    /// A prompt whose `when:` condition doesn't hold isn't asked and leaves
+    /// the variable undefined (`None`). Its default isn't filled in either,
+    /// as it may use answers to prompts that were skipped as well.
+    fn prompt(&mut self, name: &str, prompt: &Prompt, variables: &Variables) -> Result<Option<Value>, String> {
+        if let Some(condition) = &prompt.when
+            && !self.evaluate_condition(condition, variables)?
+        {
+            return Ok(None);
+        }
+        let default = self.interpolate(&prompt.default, variables)?;
+        let default = default.as_str();
+
+        let options = match &prompt.kind {
+            PromptKind::Select(options) | PromptKind::MultiSelect(options) => {
+                let options = self.evaluate(options, variables)?.items()?;
+                if options.is_empty() {
+                    return Err(format!("No options to choose from for '{}'", name));
+                }
+                options
+            }
+            _ => Vec::new(),
+        };
+
+        let question = self.interpolate(&prompt.question, variables)?;
+        let value = match self.answers.lookup(name) {
+            Some(answer) => prompt::parse_answer(&prompt.kind, &answer, &options)
+                .map_err(|e| format!("Invalid value for '{}': {}", name, e))?,
+            None if self.answers.non_interactive => {
+                // An empty selection is a fine answer to a multi-select.
+                if default.is_empty() && !matches!(prompt.kind, PromptKind::MultiSelect(_)) {
+                    return Err(format!(
+                        "No value for '{}' ({}) in non-interactive mode; pass --set {}=<value>",
+                        name, question, name
+                    ));
+                }
+                prompt::parse_answer(&prompt.kind, default, &options)
+                    .map_err(|e| format!("Invalid default for '{}': {}", name, e))?
+            }
+            None => prompt::ask(prompt, &question, default, &options)?,
+        };
+        prompt::validate(&prompt.validators, &value).map_err(|e| format!("Invalid value for '{}': {}", name, e))?;
+
+        if !matches!(prompt.kind, PromptKind::Password) {
+            self.inputs.insert(name.to_string(), value.to_string());
+        }
+        Ok(Some(value))
+    }
+
+    /// The value of `var:` text or a function argument: typed if it reads as
+    /// one (see `expr::parse_value`), text with variables substituted if not.
+    fn value_of(&self, raw: &str, variables: &Variables) -> Result<Value, String> {
+        match expr::parse_value(raw) {
+            Some(expr) => self.evaluate(&expr, variables),
+            None => Ok(Value::Str(self.interpolate(raw, variables)?)),
+        }
+    }
+
+    /// Substitutes variable references in `text` in a single pass:
+    ///
+    /// - `${expr}` is replaced by the value of any expression, e.g. `${name}`
+    ///   or `${deps[0]}`.
+    /// - `$name` takes the longest name possible, so `$name_upper` never
+    ///   means `$name` followed by `_upper`. On a map it continues with
+    ///   `.key`, on a list or map with `[index]`.
+    /// - `$$` is a literal `$`, as is a `$` not followed by a name or `{`.
+    ///
+    /// Referencing a variable that isn't defined is an error.
+    fn interpolate(&self, text: &str, variables: &Variables) -> Result<String, String> {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(dollar) = rest.find('$') {
+            result.push_str(&rest[..dollar]);
+            let after = &rest[dollar + 1..];
+
+            if let Some(after) = after.strip_prefix('$') {
+                result.push('$');
+                rest = after;
+                continue;
+            }
+
+            if let Some(inner) = after.strip_prefix('{') {
+                let close = closing_brace(inner).ok_or("Missing '}' to close '${'")?;
+                let (expr, filters) =
+                    expr::parse_embedded(&inner[..close]).map_err(|e| embedded_error(&inner[..close], e))?;
+                let value = self.evaluate_filtered(&expr, &filters, variables)?;
+                result.push_str(&value.to_string());
+                rest = &inner[close + 1..];
+                continue;
+            }
+
+            let len = name_length(after);
+            if len == 0 {
+                result.push('$');
+                rest = after;
+                continue;
+            }
+
+            let name = &after[..len];
+            let value = variables
+                .get(name)
+                .ok_or_else(|| format!("Undefined variable '${}'", name))?;
+            let (value, used) = self.follow_accessors(value.clone(), &after[len..], variables)?;
+            result.push_str(&value.to_string());
+            rest = &after[len + used..];
+        }
+
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    /// Evaluates `expr` and passes the value through `filters` in order. With
+    /// a `default` filter, an undefined variable counts as empty instead of
+    /// failing; any other error still fails.
+    fn evaluate_filtered(&self, expr: &Expr, filters: &[Filter], variables: &Variables) -> Result<Value, String> {
+        let has_default = filters.iter().any(|filter| filter.name == "default");
+        let mut value = if has_default && is_undefined(expr, variables) {
+            Value::Str(String::new())
+        } else {
+            self.evaluate(expr, variables)?
+        };
+
+        for filter in filters {
+            let args = filter
+                .args
+                .iter()
+                .map(|arg| self.evaluate(arg, variables))
+                .collect::<Result<Vec<_>, _>>()?;
+            value = filter::apply(&filter.name, value, &args)?;
+        }
+        Ok(value)
+    }
+
+    /// Applies the `.key` and `[index]` accessors at the start of `text` to
+    /// `value` for as long as it is a map or list, and returns the result
+    /// along with the number of bytes used. `$file.txt` on a string is left
+    /// alone.
+    fn follow_accessors(&self, mut value: Value, text: &str, variables: &Variables) -> Result<(Value, usize), String> {
+        let mut used = 0;
+
+        loop {
+            let rest = &text[used..];
+            match (&value, rest.chars().next()) {
+                (Value::Map(_), Some('.')) if name_length(&rest[1..]) > 0 => {
+                    let len = name_length(&rest[1..]);
+                    value = value.field(&rest[1..=len])?;
+                    used += len + 1;
+                }
+                (Value::List(_) | Value::Map(_), Some('[')) => {
+                    let close = rest.find(']').ok_or("Missing ']' to close '['")?;
+                    let index = expr::parse(&rest[1..close]).map_err(|e| embedded_error(&rest[1..close], e))?;
+                    let index = self.evaluate(&index, variables)?;
+                    value = value.index(&index)?;
+                    used += close + 1;
+                }
+                _ => return Ok((value, used)),
+            }
+        }
+    }
+
+    fn evaluate_condition(&self, condition: &Expr, variables: &Variables) -> Result<bool, String> {
+        Ok(self.evaluate(condition, variables)?.truthy())
+    }
+
+    fn evaluate(&self, expr: &Expr, variables: &Variables) -> Result<Value, String> {
+        let value = match expr {
+            Expr::Str(text) => Value::Str(self.interpolate(text, variables)?),
+            Expr::Literal(value) => value.clone(),
+            Expr::Var(name) => variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("Undefined variable '${}'", name))?,
+            Expr::List(items) => Value::List(
+                items
+                    .iter()
+                    .map(|item| self.evaluate(item, variables))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Map(entries) => Value::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), self.evaluate(value, variables)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            Expr::Index(base, index) => self.evaluate(base, variables)?.index(&self.evaluate(index, variables)?)?,
+            Expr::Field(base, name) => match self.evaluate(base, variables)? {
+                value @ Value::Map(_) => value.field(name)?,
+                // As in text, `$file.txt` on anything but a map is a suffix.
+                value => Value::Str(format!("{}.{}", value, name)),
+            },
+            Expr::Not(inner) => Value::Bool(!self.evaluate_condition(inner, variables)?),
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                Value::Bool(self.evaluate_condition(left, variables)? || self.evaluate_condition(right, variables)?)
+            }
+            Expr::Binary(BinaryOp::And, left, right) => {
+                Value::Bool(self.evaluate_condition(left, variables)? && self.evaluate_condition(right, variables)?)
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.evaluate(left, variables)?;
+                let right = self.evaluate(right, variables)?;
+
+                let result = match op {
+                    BinaryOp::Eq => left.loose_eq(&right),
+                    BinaryOp::Ne => !left.loose_eq(&right),
+                    BinaryOp::Lt => left.as_number()? < right.as_number()?,
+                    BinaryOp::Gt => left.as_number()? > right.as_number()?,
+                    BinaryOp::Le => left.as_number()? <= right.as_number()?,
+                    BinaryOp::Ge => left.as_number()? >= right.as_number()?,
+                    BinaryOp::Matches => Regex::new(&right.to_string())
+                        .map_err(|e| format!("Invalid regex '{}': {}", right, e))?
+                        .is_match(&left.to_string()),
+                    BinaryOp::Contains => match &left {
+                        Value::List(items) => items.iter().any(|item| item.loose_eq(&right)),
+                        Value::Map(entries) => entries.contains_key(&right.to_string()),
+                        _ => left.to_string().contains(&right.to_string()),
+                    },
+                    BinaryOp::StartsWith => left.to_string().starts_with(&right.to_string()),
+                    BinaryOp::EndsWith => left.to_string().ends_with(&right.to_string()),
+                    BinaryOp::Or | BinaryOp::And => unreachable!("handled above"),
+                };
+                Value::Bool(result)
+            }
+            Expr::Call(name, args) => {
+                let arg = self.evaluate(&args[0], variables)?;
+                match name.as_str() {
+                    "exists" => Value::Bool(self.executor.resolve(&arg.to_string()).exists()),
+                    "is_dir" => Value::Bool(self.executor.resolve(&arg.to_string()).is_dir()),
+                    "env" => Value::Str(std::env::var(arg.to_string()).unwrap_or_default()),
+                    "len" => Value::Number(arg.len()? as f64),
+                    _ => return Err(format!("Unknown function '{}'", name)),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn handle_if(
+        &mut self,
+        branches: &[Branch],
+        otherwise: Option<&Block>,
+        variables: &mut Variables,
+    ) -> Result<(), Diagnostic> {
+        for branch in branches {
+            let holds = self
+                .evaluate_condition(&branch.condition, variables)
+                .map_err(|e| self.template.source.error(branch.span, e))?;
+            if holds {
+                return self.execute_block(&branch.body, variables);
+            }
+        }
+
+        match otherwise {
+            Some(block) => self.execute_block(block, variables),
+            None => Ok(()),
+        }
+    }
+
+    fn handle_for(
+        &mut self,
+        statement: &Stmt,
+        var: &str,
+        iterable: &Iterable,
+        body: &Block,
+        variables: &Variables,
+    ) -> Result<(), Diagnostic> {
+        let values = self
+            .loop_values(iterable, variables)
+            .map_err(|e| self.template.source.error(statement.span, e))?;
+
+        for value in values {
+            let mut local_vars = variables.clone();
+            local_vars.insert(var.to_string(), value);
+            self.execute_block(body, &mut local_vars)?;
+        }
+        Ok(())
+    }
+
+    fn loop_values(&self, iterable: &Iterable, variables: &Variables) -> Result<Vec<Value>, String> {
+        match iterable {
+            Iterable::List(values) => values
+                .iter()
+                .map(|value| Ok(Value::Str(self.interpolate(value, variables)?)))
+                .collect(),
+            Iterable::Value(expr) => self.evaluate(expr, variables)?.items(),
+            Iterable::Range(start, end) => {
+                let bound = |text: &str| {
+                    let text = self.interpolate(text, variables)?;
+                    text.parse::<i64>()
+                        .map_err(|_| format!("Range bound '{}' is not a whole number", text))
+                };
+                Ok((bound(start)?..=bound(end)?).map(|i| Value::Number(i as f64)).collect())
+            }
+            Iterable::Glob(pattern) => {
+                let pattern = self.interpolate(pattern, variables)?;
+                // Match against where the executor is, so a dry run after a
+                // planned `cd:` looks in the right place.
+                let base = self.executor.resolve("");
+                let paths = glob::glob(&base.join(&pattern).to_string_lossy())
+                    .map_err(|e| format!("Invalid glob pattern '{}': {}", pattern, e))?;
+
+                let mut values = Vec::new();
+                for path in paths {
+                    let path = path.map_err(|e| e.to_string())?;
+                    let relative = path.strip_prefix(&base).unwrap_or(&path);
+                    values.push(Value::Str(relative.to_string_lossy().into_owned()));
+                }
+                Ok(values)
+            }
+        }
+    }
+
+    /// Runs `body` in the enclosing scope for as long as `condition` holds,
+    /// so variables set in it can end the loop.
+    fn handle_while(
+        &mut self,
+        statement: &Stmt,
+        condition: &Expr,
+        body: &Block,
+        variables: &mut Variables,
+    ) -> Result<(), Diagnostic> {
+        let mut iterations = 0;
+        loop {
+            let holds = self
+                .evaluate_condition(condition, variables)
+                .map_err(|e| self.template.source.error(statement.span, e))?;
+            if !holds {
+                return Ok(());
+            }
+
+            if iterations == MAX_WHILE_ITERATIONS {
+                return Err(self.template.source.error(
+                    statement.span,
+                    format!("while loop is still running after {} iterations", MAX_WHILE_ITERATIONS),
+                ));
+            }
+            iterations += 1;
+            self.execute_block(body, variables)?;
+        }
+    }
+
+    fn handle_try(
+        &mut self,
+        body: &Block,
+        catch: Option<&Block>,
+        variables: &mut Variables,
+    ) -> Result<(), Diagnostic> {
+        self.try_depth += 1;
+        let result = self.execute_block(body, variables);
+        self.try_depth -= 1;
+
+        let Err(diagnostic) = result else {
+            return Ok(());
+        };
+
+        match catch {
+            Some(catch) => {
+                variables.insert("error".to_string(), Value::Str(diagnostic.message));
+                self.execute_block(catch, variables)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn handle_function_call(
+        &mut self,
+        statement: &Stmt,
+        name: &str,
+        args: &[String],
+        variables: &Variables,
+    ) -> Result<(), Diagnostic> {
+        let args = args
+            .iter()
+            .map(|arg| self.value_of(arg, variables))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| self.template.source.error(statement.span, e))?;
+
+        let (func_def, owner) = match self.find_function(name) {
+            Some(found) => found,
+            None => return Err(self.template.source.error(statement.span, format!("Function '{}' not found", name))),
+        };
+
+        if args.len() != func_def.params.len() {
+            return Err(self.template.source.error(statement.span, format!(
+                "Function '{}' (defined on line {}) expects {} parameter(s), but {} were provided",
+                name,
+                func_def.span.line,
+                func_def.params.len(),
+                args.len()
+            )));
+        }
+
+        let mut local_vars = variables.clone();
+
+        for (param_name, arg_value) in func_def.params.iter().zip(args) {
+            local_vars.insert(param_name.clone(), arg_value);
+        }
+
+        let caller = std::mem::replace(&mut self.template, owner);
+        let result = self.execute_block(&func_def.body, &mut local_vars);
+        self.template = caller;
+        result
+    }
+
+    /// Finds a function in the running template or, failing that, in the
+    /// templates it imports, together with the template it is defined in.
+    fn find_function(&self, name: &str) -> Option<(&'a FunctionDefinition, &'a Template)> {
+        let template = self.template;
+        if let Some(function) = template.program.functions.get(name) {
+            return Some((function, template));
+        }
+        template.program.imports.iter().find_map(|import| {
+            let imported = self.templates.dependencies.get(import)?;
+            imported.program.functions.get(name).map(|function| (function, imported))
+        })
+    }
+
+    /// Runs another template in place, with the same variables: whatever it
+    /// sets is visible after the `include:`.
+    fn handle_include(&mut self, statement: &Stmt, name: &str, variables: &mut Variables) -> Result<(), Diagnostic> {
+        let Some(included) = self.templates.dependencies.get(name) else {
+            return Err(self.template.source.error(statement.span, format!("Template '{}' is not loaded", name)));
+        };
+
+        let caller = std::mem::replace(&mut self.template, included);
+        let result = self
+            .ask_parameters(variables)
+            .and_then(|()| self.execute_block(&included.program.body, variables));
+        self.template = caller;
+        result
+    }
+
+    fn handle_mkdir(&mut self, name: &str, variables: &Variables) -> Result<(), String> {
+        let name = self.interpolate(name, variables)?;
+        self.executor.mkdir(name.trim())
+    }
+
+    fn handle_create_file(&mut self, name: &str, variables: &Variables) -> Result<(), String> {
+        let name = self.interpolate(name, variables)?;
+        self.executor.create_file(name.trim())
+    }
+
+    fn handle_write_file(
+        &mut self,
+        path: &str,
+        content: &Content,
+        variables: &Variables,
+    ) -> Result<(), String> {
+        let file_name = self.interpolate(path, variables)?;
+
+        let content = match content {
+            Content::Heredoc(body) => self.interpolate(body, variables)?,
+            Content::Inline(line) => self
+                .interpolate(line, variables)?
+                .replace("\\n", "\n")
+                .replace("\\t", "\t")
+                .replace("\\r", "\r"),
+        };
+
+        self.executor.write_file(&file_name, &content)
+    }
+
+    fn handle_command(&mut self, lines: &[CommandLine], variables: &Variables) -> Result<(), Diagnostic> {
+        for line in lines {
+            let result = self.interpolate(&line.command, variables).and_then(|command| {
+                if command.trim().is_empty() {
+                    Err("Empty command".to_string())
+                } else {
+                    self.executor.run_command(&command)
+                }
+            });
+
+            if let Err(e) = result {
+                self.fail(self.template.source.error(line.span, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_cd(&mut self, dir: &str, variables: &Variables) -> Result<(), String> {
+        let dir = self.interpolate(dir, variables)?;
+        self.executor.cd(dir.trim())
+    }
+
+    fn handle_tree(&mut self, source: &str, target: &str, render: bool, variables: &Variables) -> Result<(), String> {
+        let source = self.interpolate(source, variables)?;
+        let target = self.interpolate(target, variables)?;
+
+        let inside = Path::new(&source)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            return Err(format!("'{}' is outside of the template's {}/ directory", source, FILES_DIR));
+        }
+        let dir = match &self.template.program.files_dir {
+            Some(files_dir) => files_dir.join(&source),
+            None => return Err(format!("This template has no {}/ directory", FILES_DIR)),
+        };
+        if !dir.is_dir() {
+            return Err(format!("'{}/{}' is not a directory in this template", FILES_DIR, source));
+        }
+
+        if !matches!(target.as_str(), "" | ".") {
+            self.executor.mkdir(&target)?;
+        }
+        self.copy_dir(&dir, Path::new(&target), render, variables)
+    }
+
+    /// `path` in the template's `files/` directory as the author wrote it.
+    fn template_path(&self, path: &Path) -> String {
+        match self.template.program.files_dir.as_ref().and_then(|files_dir| path.strip_prefix(files_dir).ok()) {
+            Some(relative) => Path::new(FILES_DIR).join(relative).display().to_string(),
+            None => path.display().to_string(),
+        }
+    }
+
+    /// Copies the contents of `dir` into `target`, substituting variables in
+    /// every name and, when rendering, in every text file. Files that aren't
+    /// UTF-8 text are copied as they are.
+    fn copy_dir(&mut self, dir: &Path, target: &Path, render: bool, variables: &Variables) -> Result<(), String> {
+        let read_error = |e: std::io::Error| format!("Could not read '{}': {}", self.template_path(dir), e);
+        let mut entries = fs::read_dir(dir)
+            .map_err(read_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(read_error)?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                return Err(format!("'{}' is not a valid UTF-8 file name", self.template_path(&path)));
+            };
+            let name = self
+                .interpolate(&name, variables)
+                .map_err(|e| format!("In the name of '{}': {}", self.template_path(&path), e))?;
+            if name.is_empty() || name.contains(['/', '\\']) {
+                return Err(format!("'{}' names '{}', which is not a valid file name", self.template_path(&path), name));
+            }
+
+            let dest = target.join(&name);
+            let dest_str = dest.to_string_lossy();
+            if path.is_dir() {
+                self.executor.mkdir(&dest_str)?;
+                self.copy_dir(&path, &dest, render, variables)?;
+                continue;
+            }
+
+            let text = if render {
+                fs::read(&path)
+                    .map_err(|e| format!("Could not read '{}': {}", self.template_path(&path), e))
+                    .map(|bytes| String::from_utf8(bytes).ok().filter(|text| !text.contains('\0')))?
+            } else {
+                None
+            };
+            match text {
+                Some(text) => {
+                    let content = self
+                        .interpolate(&text, variables)
+                        .map_err(|e| format!("In '{}': {}", self.template_path(&path), e))?;
+                    self.executor.write_file(&dest_str, &content)?;
+                }
+                None => self.executor.copy_file(&path, &dest_str)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `expr` is a variable that isn't set, or a key or item of one.
+fn is_undefined(expr: &Expr, variables: &Variables) -> bool {
+    match expr {
+        Expr::Var(name) => !variables.contains_key(name),
+        Expr::Field(value, _) | Expr::Index(value, _) => is_undefined(value, variables),
+        _ => false,
+    }
+}
+
+/// Length in bytes of the variable name at the start of `text`.
+pub fn name_length(text: &str) -> usize {
+    text.char_indices()
+        .find(|&(i, c)| !(c.is_alphabetic() || c == '_' || (i > 0 && c.is_alphanumeric())))
+        .map_or(text.len(), |(i, _)| i)
+}
+
+fn embedded_error(text: &str, error: expr::ExprError) -> String {
+    format!("Invalid expression '{}': {}", text.trim(), error.message)
+}
+
+/// Finds the `}` closing a `${`, skipping over nested braces and quoted
+/// strings.
+pub fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '{' => depth += 1,
+                '}' if depth == 0 => return Some(i),
+                '}' => depth -= 1,
+                _ => {}
+            },
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Source;
+    use crate::executor::{Action, PlanExecutor};
+    use crate::parse;
+    use std::path::PathBuf;
+
+    /// Runs `script` as a dry run and returns the directories it would
+    /// create, relative to where it runs, or the first error.
+    fn mkdirs(script: &str) -> Result<Vec<String>, String> {
+        let source = Source::new("test", script.to_string());
+        let (program, _) = parse::parse_program(&source).map_err(|d| d.0[0].message.clone())?;
+        let templates = Templates {
+            main: Template { source, program, manifest: None },
+            dependencies: HashMap::new(),
+        };
+        let options = RunOptions {
+            dry_run: true,
+            error_policy: ErrorPolicy::Abort,
+            rollback: false,
+            answers: Answers::new(&[], None, true)?,
+        };
+        let mut executor = PlanExecutor::new(PathBuf::from("/w"));
+        run(&templates, &mut executor, &options).map_err(|d| d.0[0].message.clone())?;
+
+        Ok(executor
+            .actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Mkdir(path) => path.strip_prefix("/w").ok().map(|p| p.display().to_string()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    #[test]
+    fn bare_names_take_the_longest_match() {
+        let dirs = mkdirs("var: name = app\nvar: name_upper = APP\nmkdir: $name_upper-$name\n").unwrap();
+        assert_eq!(dirs, ["APP-app"]);
+    }
+
+    #[test]
+    fn a_longer_name_is_not_a_shorter_variable() {
+        let error = mkdirs("var: name = app\nmkdir: $name_x\n").unwrap_err();
+        assert_eq!(error, "Undefined variable '$name_x'");
+    }
+
+    #[test]
+    fn braces_delimit_the_name() {
+        let dirs = mkdirs("var: name = app\nmkdir: ${name}_x\nmkdir: ${ name }x\n").unwrap();
+        assert_eq!(dirs, ["app_x", "appx"]);
+    }
+
+    #[test]
+    fn double_dollar_is_a_literal_dollar() {
+        let dirs = mkdirs("var: name = app\nmkdir: $$name-$$$name-${name}$$\n").unwrap();
+        assert_eq!(dirs, ["$name-$app-app$"]);
+    }
+
+    #[test]
+    fn substituted_values_are_not_substituted_again() {
+        let dirs = mkdirs("var: other = x\nvar: name = a$$other\nmkdir: $name\n").unwrap();
+        assert_eq!(dirs, ["a$other"]);
+    }
+
+    #[test]
+    fn default_stands_in_for_undefined_variables_only() {
+        let dirs = mkdirs("var: name = app\nmkdir: ${missing | default(\"x\")}-${name | default(\"y\")}\n").unwrap();
+        assert_eq!(dirs, ["x-app"]);
+
+        let error = mkdirs("var: items = [a, b]\nmkdir: ${items[5] | default(\"x\")}\n").unwrap_err();
+        assert_eq!(error, "Index 5 is out of range for a list of 2 item(s)");
+    }
+
+    #[test]
+    fn skipped_prompts_leave_their_variable_undefined() {
+        let script = "var: use_db = confirm(\"Database?\", no)\n\
+            var: db = select(\"Which?\", [postgres, mysql], postgres) when: $use_db\n\
+            var: db_name = input(\"Name\", app_$db) when: $use_db\n\
+            mkdir: ${db_name | default(\"none\")}\n";
+        assert_eq!(mkdirs(script).unwrap(), ["none"]);
+    }
+
+    #[test]
+    fn dots_pick_keys_from_maps_and_are_text_on_anything_else() {
+        let script = "var: name = app\nvar: db = {name: pg}\n\
+            if: $name.tar.gz == app.tar.gz && $db.name == pg {\n    mkdir: ${name.rs}-${db.name}\n}\n";
+        assert_eq!(mkdirs(script).unwrap(), ["app.rs-pg"]);
+
+        let error = mkdirs("var: db = {name: pg}\nmkdir: ${db.port}\n").unwrap_err();
+        assert_eq!(error, "No key 'port' in map");
+    }
+
+    #[test]
+    fn commands_that_substitute_to_nothing_are_errors() {
+        let error = mkdirs("command\n- ${missing | default(\"  \")}\nend_command\n").unwrap_err();
+        assert_eq!(error, "Empty command");
+    }
+}
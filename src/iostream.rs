@@ -1,5 +1,7 @@
-use inquire::Text;
+use inquire::{Confirm, CustomType, MultiSelect, Password, PasswordDisplayMode, Select, Text};
 use inquire::formatter::StringFormatter;
+use inquire::list_option::ListOption;
+use inquire::validator::Validation;
 use inquire::ui::{RenderConfig, Styled, StyleSheet, Color};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::thread;
@@ -7,11 +9,33 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
+use std::rc::Rc;
 use std::time::Duration;
 
+/// Checks an answer before it is accepted. If it fails, the message is shown
+/// and the question stays open.
+pub type Check<T> = Rc<dyn Fn(&T) -> Result<(), String>>;
+
+fn validation(result: Result<(), String>) -> Validation {
+    match result {
+        Ok(()) => Validation::Valid,
+        Err(message) => Validation::Invalid(message.into()),
+    }
+}
+
 pub fn get_input_text(
     question: &str,
     default: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    get_checked_text(question, default, Rc::new(|_| Ok(())))
+}
+
+/// Like `get_input_text`, but only accepts answers that pass `check`. An
+/// empty answer stands for `default`, so that is what gets checked.
+pub fn get_checked_text(
+    question: &str,
+    default: &str,
+    check: Check<str>,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let render_config = RenderConfig::default_colored()
         .with_prompt_prefix(Styled::new("?").with_fg(Color::LightCyan))
@@ -28,13 +52,14 @@ pub fn get_input_text(
         }
     };
 
-
-    let spaces = " ".repeat(default.len());
-
     let input = Text::new(question)
-        .with_placeholder(&format!("{} {}", "»", default).to_string())
+        .with_placeholder(&format!("{} {}", "»", default))
         .with_render_config(render_config)
         .with_formatter(formatter)
+        .with_validator({
+            let default = default.to_string();
+            move |input: &str| Ok(validation(check(if input.is_empty() { &default } else { input })))
+        })
         .prompt()?;
 
     let input = if input.is_empty() {
@@ -46,6 +71,82 @@ pub fn get_input_text(
     Ok(input)
 }
 
+fn prompt_config() -> RenderConfig<'static> {
+    RenderConfig::default_colored()
+        .with_prompt_prefix(Styled::new("?").with_fg(Color::LightCyan))
+        .with_answered_prompt_prefix(Styled::new("√").with_fg(Color::LightGreen))
+}
+
+pub fn get_confirm(question: &str, default: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    let answer = Confirm::new(question)
+        .with_default(default)
+        .with_render_config(prompt_config())
+        .prompt()?;
+
+    Ok(answer)
+}
+
+pub fn get_password(question: &str, check: Check<str>) -> Result<String, Box<dyn std::error::Error>> {
+    let answer = Password::new(question)
+        .with_validator(move |input: &str| Ok(validation(check(input))))
+        .without_confirmation()
+        .with_display_mode(PasswordDisplayMode::Masked)
+        .with_display_toggle_enabled()
+        .with_render_config(prompt_config())
+        .prompt()?;
+
+    Ok(answer)
+}
+
+pub fn get_number(
+    question: &str,
+    default: Option<f64>,
+    check: Check<f64>,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let mut prompt = CustomType::<f64>::new(question)
+        .with_validator(move |input: &f64| Ok(validation(check(input))))
+        .with_error_message("Please type a number")
+        .with_render_config(prompt_config());
+    if let Some(default) = default {
+        prompt = prompt.with_default(default);
+    }
+
+    Ok(prompt.prompt()?)
+}
+
+/// Lets the user pick one of `options` and returns its index.
+pub fn get_select(
+    question: &str,
+    options: Vec<String>,
+    default: Option<usize>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let answer = Select::new(question, options)
+        .with_starting_cursor(default.unwrap_or(0))
+        .with_render_config(prompt_config())
+        .raw_prompt()?;
+
+    Ok(answer.index)
+}
+
+/// Lets the user pick any number of `options` and returns their indices.
+pub fn get_multiselect(
+    question: &str,
+    options: Vec<String>,
+    defaults: &[usize],
+    check: Check<[usize]>,
+) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+    let answer = MultiSelect::new(question, options)
+        .with_default(defaults)
+        .with_validator(move |selected: &[ListOption<&String>]| {
+            let indices: Vec<usize> = selected.iter().map(|option| option.index).collect();
+            Ok(validation(check(&indices)))
+        })
+        .with_render_config(prompt_config())
+        .raw_prompt()?;
+
+    Ok(answer.into_iter().map(|option| option.index).collect())
+}
+
 pub fn show_loader(message: &str, running: Arc<AtomicBool>) {
     let pb = ProgressBar::new_spinner();
     pb.set_style(
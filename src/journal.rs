@@ -0,0 +1,109 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+enum Entry {
+    CreatedDir(PathBuf),
+    CreatedFile(PathBuf),
+    Overwrote { path: PathBuf, backup: PathBuf },
+}
+
+/// Records every path a template run creates or overwrites so the run can be
+/// undone if it fails. Overwritten files are copied to a backup directory
+/// before they are touched.
+pub struct Journal {
+    entries: Vec<Entry>,
+    start_dir: PathBuf,
+    backup_dir: PathBuf,
+}
+
+impl Journal {
+    pub fn new() -> io::Result<Self> {
+        Ok(Journal {
+            entries: Vec::new(),
+            start_dir: env::current_dir()?,
+            backup_dir: env::temp_dir().join(format!("tmpl-backup-{}", std::process::id())),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Call before `fs::create_dir_all(path)`: records every missing directory
+    /// on the way to `path`, outermost first.
+    pub fn before_create_dir(&mut self, path: &Path) -> io::Result<()> {
+        let path = env::current_dir()?.join(path);
+        let mut missing: Vec<PathBuf> = path
+            .ancestors()
+            .take_while(|p| !p.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        missing.reverse();
+
+        self.entries.extend(missing.into_iter().map(Entry::CreatedDir));
+        Ok(())
+    }
+
+    /// Call before creating or truncating the file at `path`.
+    pub fn before_write_file(&mut self, path: &Path) -> io::Result<()> {
+        let path = env::current_dir()?.join(path);
+
+        if !path.exists() {
+            self.entries.push(Entry::CreatedFile(path));
+            return Ok(());
+        }
+
+        if self.entries.iter().any(|e| matches!(e, Entry::Overwrote { path: p, .. } | Entry::CreatedFile(p) if *p == path)) {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.backup_dir)?;
+        let backup = self.backup_dir.join(self.entries.len().to_string());
+        fs::copy(&path, &backup)?;
+        self.entries.push(Entry::Overwrote { path, backup });
+        Ok(())
+    }
+
+    /// Undoes every recorded change, newest first. Directories the run created
+    /// are removed with everything in them, since nothing in them predates the
+    /// run. Returns the paths that could not be restored.
+    pub fn rollback(&mut self) -> Vec<(PathBuf, io::Error)> {
+        let _ = env::set_current_dir(&self.start_dir);
+        let mut failures = Vec::new();
+
+        for entry in self.entries.drain(..).rev() {
+            let (path, result) = match entry {
+                Entry::CreatedDir(path) => {
+                    let result = if path.exists() { fs::remove_dir_all(&path) } else { Ok(()) };
+                    (path, result)
+                }
+                Entry::CreatedFile(path) => {
+                    let result = if path.exists() { fs::remove_file(&path) } else { Ok(()) };
+                    (path, result)
+                }
+                Entry::Overwrote { path, backup } => {
+                    let result = fs::copy(&backup, &path).map(|_| ());
+                    (path, result)
+                }
+            };
+
+            if let Err(e) = result {
+                failures.push((path, e));
+            }
+        }
+
+        failures
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.backup_dir);
+    }
+}
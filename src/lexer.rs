@@ -0,0 +1,432 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    Var,
+    Mkdir,
+    CreateFile,
+    WriteFile,
+    Cd,
+    CopyTree,
+    RenderTree,
+    If,
+    ElseIf,
+    Else,
+    For,
+    While,
+    Function,
+    Include,
+    Import,
+    Command,
+    EndCommand,
+    Try,
+    Catch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Ident(String),
+    Text(String),
+    Heredoc(String),
+    CommandLine(String),
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Colon,
+    Equals,
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+/// Keywords that stand alone on a line, optionally followed by `{`.
+const BLOCK_KEYWORDS: &[(&str, Keyword)] = &[
+    ("try", Keyword::Try),
+    ("catch", Keyword::Catch),
+    ("else", Keyword::Else),
+];
+
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("var:", Keyword::Var),
+    ("mkdir:", Keyword::Mkdir),
+    ("create_file:", Keyword::CreateFile),
+    ("write_file", Keyword::WriteFile),
+    ("cd:", Keyword::Cd),
+    ("copy_tree:", Keyword::CopyTree),
+    ("render_tree:", Keyword::RenderTree),
+    ("if:", Keyword::If),
+    ("else if:", Keyword::ElseIf),
+    ("for:", Keyword::For),
+    ("while:", Keyword::While),
+    ("function:", Keyword::Function),
+    ("include:", Keyword::Include),
+    ("import:", Keyword::Import),
+];
+
+/// Splits a template into tokens.
+///
+/// The language is line oriented, so the lexer works one line at a time and
+/// decides how to tokenize the rest of a line from the keyword it starts with.
+/// Free-form parts such as paths, conditions or file contents are kept as a
+/// single `Text` token and interpreted later.
+///
+/// A line that cannot be tokenized is reported and skipped, so one pass finds
+/// every lexical error in the template.
+pub fn tokenize(source: &str) -> (Vec<Token>, Vec<LexError>) {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut command_start = None;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let raw = lines[i];
+        let line_no = i + 1;
+        i += 1;
+
+        let column = raw.len() - raw.trim_start().len() + 1;
+        let rest = raw.trim();
+
+        if rest.is_empty() || rest.starts_with("//") || rest.starts_with('#') {
+            continue;
+        }
+
+        if command_start.is_some() {
+            if rest == "end_command" {
+                command_start = None;
+                push(&mut tokens, TokenKind::Keyword(Keyword::EndCommand), line_no, column);
+            } else if let Some(command) = rest.strip_prefix('-') {
+                push(&mut tokens, TokenKind::CommandLine(command.trim().to_string()), line_no, column);
+            } else {
+                errors.push(LexError {
+                    message: format!("Expected '- <command>' or 'end_command', found '{}'", rest),
+                    span: Span { line: line_no, column },
+                });
+                continue;
+            }
+            push(&mut tokens, TokenKind::Newline, line_no, column + rest.len());
+            continue;
+        }
+
+        let line_start = tokens.len();
+        match lex_line(rest, line_no, column, &lines, &mut i, &mut tokens) {
+            Ok(()) => {
+                if tokens[line_start..].iter().any(|t| t.kind == TokenKind::Keyword(Keyword::Command)) {
+                    command_start = Some(Span { line: line_no, column });
+                }
+            }
+            Err(e) => {
+                tokens.truncate(line_start);
+                errors.push(e);
+            }
+        }
+    }
+
+    if let Some(span) = command_start {
+        errors.push(LexError {
+            message: "Missing 'end_command' for command block".to_string(),
+            span,
+        });
+    }
+
+    push(&mut tokens, TokenKind::Eof, lines.len() + 1, 1);
+    (tokens, errors)
+}
+
+/// Tokenizes one (trimmed) line outside of a command block. `next_line` is
+/// advanced when the statement spans several lines, as heredocs do.
+fn lex_line(
+    line: &str,
+    line_no: usize,
+    column: usize,
+    lines: &[&str],
+    next_line: &mut usize,
+    tokens: &mut Vec<Token>,
+) -> Result<(), LexError> {
+    let mut column = column;
+    let mut rest = line;
+
+    while let Some(after) = rest.strip_prefix('}') {
+        push(tokens, TokenKind::RBrace, line_no, column);
+        let trimmed = after.trim_start();
+        column += rest.len() - trimmed.len();
+        rest = trimmed;
+    }
+
+    if rest.is_empty() {
+        push(tokens, TokenKind::Newline, line_no, column);
+        return Ok(());
+    }
+
+    if rest == "{" {
+        push(tokens, TokenKind::LBrace, line_no, column);
+    } else if rest == "command" {
+        push(tokens, TokenKind::Keyword(Keyword::Command), line_no, column);
+    } else if rest == "end_command" {
+        return Err(LexError {
+            message: "'end_command' without a matching 'command'".to_string(),
+            span: Span { line: line_no, column },
+        });
+    } else if let Some((_, keyword)) = BLOCK_KEYWORDS.iter().find(|(word, _)| {
+        rest.strip_prefix(word)
+            .is_some_and(|after| matches!(after.trim_start(), "" | "{"))
+    }) {
+        push(tokens, TokenKind::Keyword(*keyword), line_no, column);
+        if rest.ends_with('{') {
+            push(tokens, TokenKind::LBrace, line_no, column + rest.len() - 1);
+        }
+    } else if let Some((prefix, keyword)) = KEYWORDS.iter().find(|(p, _)| rest.starts_with(p)) {
+        push(tokens, TokenKind::Keyword(*keyword), line_no, column);
+        let body = &rest[prefix.len()..];
+        let body_column = column + prefix.len();
+
+        match keyword {
+            Keyword::Var => lex_var(body, line_no, body_column, tokens)?,
+            Keyword::If | Keyword::ElseIf | Keyword::For | Keyword::While => lex_block_header(body, line_no, body_column, tokens),
+            Keyword::Function => lex_function(body, line_no, body_column, tokens)?,
+            Keyword::WriteFile => {
+                lex_write_file(body, lines, next_line, line_no, body_column, tokens)?;
+            }
+            _ => push_text(body, line_no, body_column, tokens),
+        }
+    } else if let Some(paren) = rest.find('(').filter(|_| rest.ends_with(')')) {
+        let name = rest[..paren].trim();
+        if !is_identifier(name) {
+            return Err(LexError {
+                message: format!("Unknown command: {}", rest),
+                span: Span { line: line_no, column },
+            });
+        }
+        push(tokens, TokenKind::Ident(name.to_string()), line_no, column);
+        push(tokens, TokenKind::LParen, line_no, column + paren);
+        push_text(&rest[paren + 1..rest.len() - 1], line_no, column + paren + 1, tokens);
+        push(tokens, TokenKind::RParen, line_no, column + rest.len() - 1);
+    } else {
+        return Err(LexError {
+            message: format!("Unknown command: {}", rest),
+            span: Span { line: line_no, column },
+        });
+    }
+
+    push(tokens, TokenKind::Newline, line_no, column + rest.len());
+    Ok(())
+}
+
+pub fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn push(tokens: &mut Vec<Token>, kind: TokenKind, line: usize, column: usize) {
+    tokens.push(Token { kind, span: Span { line, column } });
+}
+
+fn push_text(text: &str, line: usize, column: usize, tokens: &mut Vec<Token>) {
+    let trimmed = text.trim_start();
+    let column = column + text.len() - trimmed.len();
+    push(tokens, TokenKind::Text(trimmed.trim_end().to_string()), line, column);
+}
+
+fn lex_var(body: &str, line: usize, column: usize, tokens: &mut Vec<Token>) -> Result<(), LexError> {
+    let (name, value) = match body.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (body, None),
+    };
+
+    let name_trimmed = name.trim();
+    let name_column = column + name.len() - name.trim_start().len();
+    if !is_identifier(name_trimmed) {
+        return Err(LexError {
+            message: format!("Invalid variable name '{}'", name_trimmed),
+            span: Span { line, column: name_column },
+        });
+    }
+    push(tokens, TokenKind::Ident(name_trimmed.to_string()), line, name_column);
+
+    if let Some(value) = value {
+        push(tokens, TokenKind::Equals, line, column + name.len());
+        push_text(value, line, column + name.len() + 1, tokens);
+    }
+
+    Ok(())
+}
+
+/// Pushes the header of a block statement (e.g. an `if:` condition) as text,
+/// followed by an opening brace when the block starts on the same line.
+fn lex_block_header(body: &str, line: usize, column: usize, tokens: &mut Vec<Token>) {
+    let trimmed = body.trim_end();
+    match trimmed.strip_suffix('{') {
+        Some(header) => {
+            push_text(header, line, column, tokens);
+            push(tokens, TokenKind::LBrace, line, column + trimmed.len() - 1);
+        }
+        None => push_text(body, line, column, tokens),
+    }
+}
+
+fn lex_function(body: &str, line: usize, column: usize, tokens: &mut Vec<Token>) -> Result<(), LexError> {
+    let trimmed = body.trim_end();
+    let (decl, has_brace) = match trimmed.strip_suffix('{') {
+        Some(decl) => (decl, true),
+        None => (trimmed, false),
+    };
+
+    let (name, params) = match decl.find('(') {
+        Some(paren) => (&decl[..paren], Some(paren)),
+        None => (decl, None),
+    };
+
+    let name_column = column + name.len() - name.trim_start().len();
+    if !is_identifier(name.trim()) {
+        return Err(LexError {
+            message: format!("Invalid function name '{}'", name.trim()),
+            span: Span { line, column: name_column },
+        });
+    }
+    push(tokens, TokenKind::Ident(name.trim().to_string()), line, name_column);
+
+    if let Some(paren) = params {
+        let close = match decl.rfind(')') {
+            Some(close) if close > paren => close,
+            _ => {
+                return Err(LexError {
+                    message: "Missing ')' in function declaration".to_string(),
+                    span: Span { line, column: column + paren },
+                });
+            }
+        };
+
+        push(tokens, TokenKind::LParen, line, column + paren);
+        let mut offset = paren + 1;
+        for (index, param) in decl[paren + 1..close].split(',').enumerate() {
+            if index > 0 {
+                push(tokens, TokenKind::Comma, line, column + offset - 1);
+            }
+            let param_column = column + offset + param.len() - param.trim_start().len();
+            if !param.trim().is_empty() {
+                push(tokens, TokenKind::Ident(param.trim().to_string()), line, param_column);
+            }
+            offset += param.len() + 1;
+        }
+        push(tokens, TokenKind::RParen, line, column + close);
+    }
+
+    if has_brace {
+        push(tokens, TokenKind::LBrace, line, column + trimmed.len() - 1);
+    }
+
+    Ok(())
+}
+
+/// Tokenizes `write_file(<path>): <content>`. When the content is `<<EOF`,
+/// the following lines up to `EOF>>` are consumed as a single heredoc token
+/// and `next_line` is moved past them.
+fn lex_write_file(
+    body: &str,
+    lines: &[&str],
+    next_line: &mut usize,
+    line: usize,
+    column: usize,
+    tokens: &mut Vec<Token>,
+) -> Result<(), LexError> {
+    let (path, content) = match body.strip_prefix('(').and_then(|b| b.split_once("):")) {
+        Some(parts) => parts,
+        None => {
+            return Err(LexError {
+                message: "Invalid write_file syntax, expected 'write_file(<path>): <content>'".to_string(),
+                span: Span { line, column },
+            });
+        }
+    };
+
+    push(tokens, TokenKind::LParen, line, column);
+    push_text(path, line, column + 1, tokens);
+    push(tokens, TokenKind::RParen, line, column + 1 + path.len());
+    push(tokens, TokenKind::Colon, line, column + 2 + path.len());
+
+    let content_column = column + 3 + path.len();
+    if content.trim() != "<<EOF" {
+        push_text(content, line, content_column, tokens);
+        return Ok(());
+    }
+
+    let mut heredoc = String::new();
+    let mut i = *next_line;
+    while i < lines.len() {
+        if lines[i].trim() == "EOF>>" {
+            push(tokens, TokenKind::Heredoc(heredoc), line, content_column);
+            *next_line = i + 1;
+            return Ok(());
+        }
+        heredoc.push_str(lines[i]);
+        heredoc.push('\n');
+        i += 1;
+    }
+
+    // Nothing after an unterminated heredoc can be told apart from its body.
+    *next_line = lines.len();
+    Err(LexError {
+        message: "write_file heredoc started but EOF>> not found".to_string(),
+        span: Span { line, column: content_column },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        let (tokens, errors) = tokenize(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+        tokens.into_iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn else_if_closes_and_opens_a_block_on_one_line() {
+        assert_eq!(
+            kinds("} else if: $a == b {\n"),
+            [
+                TokenKind::RBrace,
+                TokenKind::Keyword(Keyword::ElseIf),
+                TokenKind::Text("$a == b".to_string()),
+                TokenKind::LBrace,
+                TokenKind::Newline,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn heredoc_keeps_its_lines_verbatim() {
+        let kinds = kinds("write_file(a.txt): <<EOF\n# not a comment\n  indented\nEOF>>\nmkdir: b\n");
+        assert!(kinds.contains(&TokenKind::Heredoc("# not a comment\n  indented\n".to_string())));
+        assert!(kinds.contains(&TokenKind::Keyword(Keyword::Mkdir)));
+    }
+
+    #[test]
+    fn bad_lines_are_reported_and_skipped() {
+        let (tokens, errors) = tokenize("mkdir: a\nnonsense here\nvar: 1x = 2\nmkdir: b\n");
+        let lines: Vec<usize> = errors.iter().map(|error| error.span.line).collect();
+        assert_eq!(lines, [2, 3]);
+        let mkdirs = tokens.iter().filter(|t| t.kind == TokenKind::Keyword(Keyword::Mkdir)).count();
+        assert_eq!(mkdirs, 2);
+    }
+}
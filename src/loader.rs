@@ -0,0 +1,134 @@
+use crate::ast::Program;
+use crate::diagnostic::{Diagnostic, Diagnostics, Severity, Source};
+use crate::manifest::{self, Manifest, MANIFEST_FILE};
+use crate::parse::{self, FILES_DIR};
+use std::collections::{HashMap, HashSet};
+
+/// A parsed template with its source and manifest, ready to run.
+pub struct Template {
+    pub source: Source,
+    pub program: Program,
+    pub manifest: Option<Manifest>,
+}
+
+/// A template and every template it includes or imports, directly or
+/// through another one. All of them are parsed before anything runs.
+pub struct Templates {
+    pub main: Template,
+    pub dependencies: HashMap<String, Template>,
+}
+
+/// Loads the installed template `name` and its dependencies. On success
+/// the templates are returned with any warnings; if there is at least one
+/// error, all diagnostics are returned.
+pub fn load(name: &str) -> Result<(Templates, Diagnostics), Diagnostics> {
+    let (main, mut diagnostics) = load_template(name)?;
+
+    let mut loader = Loader {
+        dependencies: HashMap::new(),
+        failed: HashSet::new(),
+        stack: vec![name.to_string()],
+        diagnostics: Diagnostics::default(),
+    };
+    loader.load_dependencies(&main);
+    diagnostics.0.extend(loader.diagnostics.0);
+
+    if main.manifest.is_some() {
+        let dependencies: Vec<&Template> = loader.dependencies.values().collect();
+        diagnostics.0.extend(manifest::check_parameters(&main, &dependencies).0);
+    }
+
+    if diagnostics.error_count() > 0 {
+        return Err(diagnostics);
+    }
+
+    let templates = Templates {
+        main,
+        dependencies: loader.dependencies,
+    };
+    Ok((templates, diagnostics))
+}
+
+/// Reads and parses one installed template together with its manifest.
+fn load_template(name: &str) -> Result<(Template, Diagnostics), Diagnostics> {
+    let text = parse::parse_file(name)
+        .map_err(|e| Diagnostic::template_error(name, format!("Could not read template: {}", e)))?;
+    let source = Source::new(name, text);
+    let (mut program, warnings) = parse::parse_program(&source)?;
+
+    let dir = parse::template_dir(name);
+    program.files_dir = Some(dir.join(FILES_DIR));
+
+    let manifest = Manifest::load(&dir).map_err(|e| Diagnostic::template_error(name, e))?;
+    if let Some(manifest) = &manifest {
+        manifest.check_compatible().map_err(|e| Diagnostic::template_error(name, e))?;
+        program.parameters = manifest.parameters().map_err(|e| Diagnostic::template_error(name, e))?;
+    }
+
+    Ok((Template { source, program, manifest }, warnings))
+}
+
+struct Loader {
+    dependencies: HashMap<String, Template>,
+    /// Dependencies that could not be loaded, so they are reported once.
+    failed: HashSet<String>,
+    /// The chain of templates currently being loaded, to find cycles.
+    stack: Vec<String>,
+    diagnostics: Diagnostics,
+}
+
+impl Loader {
+    fn load_dependencies(&mut self, template: &Template) {
+        for (name, span) in &template.program.dependencies {
+            if let Some(manifest) = &template.manifest
+                && !manifest.dependencies.contains_key(name)
+            {
+                self.diagnostics.push(template.source.diagnostic(
+                    Severity::Warning,
+                    *span,
+                    format!(
+                        "'{}' is not listed in the [dependencies] of {}, so it isn't installed along with this template",
+                        name, MANIFEST_FILE
+                    ),
+                ));
+            }
+
+            if let Some(start) = self.stack.iter().position(|loading| loading == name) {
+                let cycle: Vec<&str> = self.stack[start..].iter().chain([name]).map(String::as_str).collect();
+                self.diagnostics.push(template.source.error(
+                    *span,
+                    format!("Template '{}' depends on itself: {}", name, cycle.join(" -> ")),
+                ));
+                continue;
+            }
+            if self.dependencies.contains_key(name) || self.failed.contains(name) {
+                continue;
+            }
+
+            if !parse::template_dir(name).join("file.tmpl").is_file() {
+                self.diagnostics.push(template.source.error(
+                    *span,
+                    format!("Template '{}' is not installed; install it with `tmpl install {}`", name, name),
+                ));
+                self.failed.insert(name.clone());
+                continue;
+            }
+
+            match load_template(name) {
+                Ok((dependency, warnings)) => {
+                    self.diagnostics.0.extend(warnings.0);
+                    self.stack.push(name.clone());
+                    self.load_dependencies(&dependency);
+                    self.stack.pop();
+                    self.dependencies.insert(name.clone(), dependency);
+                }
+                Err(errors) => {
+                    self.diagnostics
+                        .push(template.source.error(*span, format!("Template '{}' could not be loaded", name)));
+                    self.diagnostics.0.extend(errors.0);
+                    self.failed.insert(name.clone());
+                }
+            }
+        }
+    }
+}
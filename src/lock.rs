@@ -0,0 +1,227 @@
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::loader::Templates;
+use crate::manifest::Manifest;
+use crate::parse;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// File in a project pinning the templates that were run in it.
+pub const LOCK_FILE: &str = "tmpl.lock";
+
+/// File in an installed template's directory recording what was installed.
+pub const INSTALL_FILE: &str = "installed.toml";
+
+/// What was installed for a template: the exact version, where it came from
+/// and a hash of its content.
+#[derive(Serialize, Deserialize)]
+pub struct InstallRecord {
+    pub version: Option<Version>,
+    pub source: String,
+    pub hash: String,
+}
+
+impl InstallRecord {
+    /// Records the template in `dir` as it is now.
+    pub fn create(dir: &Path, version: Option<&Version>, source: &str) -> Result<Self, String> {
+        let record = InstallRecord {
+            version: version.cloned(),
+            source: source.to_string(),
+            hash: tree_hash(dir).map_err(|e| format!("Could not hash {}: {}", dir.display(), e))?,
+        };
+        record.save(dir)?;
+        Ok(record)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        let path = dir.join(INSTALL_FILE);
+        fs::write(&path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    pub fn load(name: &str) -> Option<Self> {
+        let text = fs::read_to_string(parse::template_dir(name).join(INSTALL_FILE)).ok()?;
+        toml::from_str(&text).ok()
+    }
+}
+
+/// A template pinned in `tmpl.lock`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Locked {
+    pub version: Option<Version>,
+    pub hash: String,
+}
+
+impl Locked {
+    /// The installed template `name` as it would be pinned. The hash is
+    /// taken from the files, so local edits show up as a change.
+    pub fn installed(name: &str) -> Result<Self, String> {
+        let dir = parse::template_dir(name);
+        let hash = tree_hash(&dir).map_err(|e| format!("Could not hash template '{}': {}", name, e))?;
+        let version = match InstallRecord::load(name) {
+            Some(record) => record.version,
+            None => Manifest::load(&dir).ok().flatten().map(|manifest| manifest.version),
+        };
+        Ok(Locked { version, hash })
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{} ({})", version, short_hash(&self.hash)),
+            None => short_hash(&self.hash).to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Lockfile {
+    #[serde(default)]
+    pub templates: BTreeMap<String, Locked>,
+}
+
+impl Lockfile {
+    /// Reads `tmpl.lock` from `dir`; `None` if the project has none.
+    pub fn load(dir: &Path) -> Result<Option<Self>, String> {
+        let path = dir.join(LOCK_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        let lockfile: Lockfile =
+            toml::from_str(&text).map_err(|e| format!("Invalid {}: {}", path.display(), e.message()))?;
+        for name in lockfile.templates.keys() {
+            parse::validate_template_name(name).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        }
+        Ok(Some(lockfile))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let path = dir.join(LOCK_FILE);
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        let text = format!("# Written by tmpl. Commit it so everyone runs the same template versions.\n\n{}", text);
+        fs::write(&path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    /// Sets the entries of `names` to the templates as they are installed now.
+    pub fn pin<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) -> Result<(), String> {
+        for name in names {
+            self.templates.insert(name.to_string(), Locked::installed(name)?);
+        }
+        Ok(())
+    }
+}
+
+/// Fails if `dir` has a `tmpl.lock` pinning any of `templates` to something
+/// other than what is installed.
+pub fn check(dir: &Path, templates: &Templates) -> Result<(), Diagnostics> {
+    let main = &templates.main.source.name;
+    let lockfile = Lockfile::load(dir).map_err(|e| Diagnostic::template_error(main, e))?;
+    let Some(lockfile) = lockfile else {
+        return Ok(());
+    };
+
+    let mut diagnostics = Diagnostics::default();
+    for name in names(templates) {
+        let Some(locked) = lockfile.templates.get(name) else {
+            continue;
+        };
+        let installed = Locked::installed(name).map_err(|e| Diagnostic::template_error(name, e))?;
+        if installed != *locked {
+            diagnostics.push(Diagnostic::template_error(
+                name,
+                format!(
+                    "{} pins '{}' to {}, but {} is installed; run `tmpl install` to install the pinned version or `tmpl update {}` to update it and the lock",
+                    LOCK_FILE,
+                    name,
+                    locked.describe(),
+                    installed.describe(),
+                    name
+                ),
+            ));
+        }
+    }
+
+    if diagnostics.error_count() > 0 {
+        Err(diagnostics)
+    } else {
+        Ok(())
+    }
+}
+
+/// Adds `templates` to the `tmpl.lock` in `dir`, creating it if needed.
+/// Templates that are already pinned keep their entry.
+pub fn record(dir: &Path, templates: &Templates) -> Result<(), String> {
+    let mut lockfile = Lockfile::load(dir)?.unwrap_or_default();
+    let missing: Vec<&str> = names(templates)
+        .filter(|name| !lockfile.templates.contains_key(*name))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    lockfile.pin(missing)?;
+    lockfile.save(dir)
+}
+
+fn names(templates: &Templates) -> impl Iterator<Item = &str> {
+    std::iter::once(templates.main.source.name.as_str()).chain(templates.dependencies.keys().map(String::as_str))
+}
+
+/// Hashes every file of the template in `dir` except the install record,
+/// in a fixed order, so the same content always gives the same hash.
+pub fn tree_hash(dir: &Path) -> io::Result<String> {
+    let mut files = Vec::new();
+    collect_files(dir, "", &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for file in files {
+        let contents = fs::read(dir.join(&file))?;
+        hasher.update(file.as_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &format!("{}/", path), files)?;
+        } else if path != INSTALL_FILE {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn short_hash(hash: &str) -> &str {
+    let digits = hash.strip_prefix("sha256:").unwrap_or(hash);
+    &digits[..digits.len().min(12)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn pinned_names_must_be_template_names() {
+        let project = TempDir::new().unwrap();
+        fs::write(
+            project.path().join(LOCK_FILE),
+            "[templates.\"../../x\"]\nhash = \"sha256:00\"\n",
+        )
+        .unwrap();
+        let error = Lockfile::load(project.path()).err().unwrap();
+        assert!(error.contains("Invalid template name '../../x'"), "{}", error);
+    }
+}
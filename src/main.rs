@@ -1,11 +1,36 @@
-use indicatif::{ProgressBar, ProgressStyle};
+mod answers;
+mod ast;
+mod config;
+mod dependencies;
+mod diagnostic;
+mod executor;
+mod expr;
+mod filter;
+mod interpreter;
 mod iostream;
+mod journal;
+mod lexer;
+mod loader;
+mod lock;
+mod manifest;
 mod parse;
+mod prompt;
+mod record;
+mod registry;
+mod source;
+mod value;
 use clap::Parser;
 use clap::Subcommand;
-use std::path::PathBuf;
-use std::{fs::File, io::Write, io::Read};
-use reqwest::blocking::Client;
+use answers::Answers;
+use dependencies::{Origin, Resolution};
+use lock::{InstallRecord, Locked, Lockfile, LOCK_FILE};
+use manifest::{Manifest, MANIFEST_FILE};
+use parse::FILES_DIR;
+use registry::{Listing, Registry};
+use semver::{Version, VersionReq};
+use interpreter::{ErrorPolicy, RunOptions};
+use record::AnswerRecord;
+use std::path::{Path, PathBuf};
 
 
 #[derive(Parser)]
@@ -16,17 +41,68 @@ struct Args {
     command: Option<Commands>,
 
     tmpl: Option<String>,
+
+    /// Print the planned filesystem and command actions without executing them
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Continue with the rest of the template when a step fails
+    #[arg(long, global = true)]
+    keep_going: bool,
+
+    /// Leave created and overwritten files in place when the template fails
+    #[arg(long, global = true)]
+    no_rollback: bool,
+
+    /// Answer an input() prompt, e.g. `--set folder=my-app` (repeatable)
+    #[arg(long = "set", value_name = "NAME=VALUE", global = true)]
+    sets: Vec<String>,
+
+    /// Read input() answers from a TOML file of `name = "value"` pairs
+    #[arg(long, value_name = "FILE", global = true)]
+    answers: Option<PathBuf>,
+
+    /// Never prompt; take inputs from --set, --answers, TMPL_VAR_<NAME> or their defaults
+    #[arg(long, global = true)]
+    non_interactive: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Install a template, e.g. `express-api`, `express-api@1.2.0` or `express-api@^1.2`.
+    /// Without a name, install the versions pinned in the project's tmpl.lock
     Install {
-        name: String,
+        name: Option<String>,
+    },
+    /// Update a template, or every installed one, to its newest version and move tmpl.lock along
+    Update {
+        name: Option<String>,
     },
     Remove {
         name: String,
     },
-    List {}
+    List {},
+    /// Search the registries for templates by name, description, author or tag
+    Search {
+        query: Option<String>,
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a template in the registry with its versions
+    Info {
+        name: String,
+        /// Print the details as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Re-run a template with the answers recorded in a .tmpl-answers.json file
+    ///
+    /// Prompts without a recorded answer, like passwords, are asked or taken
+    /// from --set, --answers and TMPL_VAR_<NAME>.
+    Replay {
+        file: PathBuf,
+    },
 }
 
 fn main() {
@@ -34,8 +110,19 @@ fn main() {
 
     match &args.command {
         Some(Commands::Install { name }) => {
-            if let Err(e) = download_tmpl(name) {
+            let result = match name {
+                Some(name) => download_tmpl(name),
+                None => install_locked(),
+            };
+            if let Err(e) = result {
                 eprintln!("Error downloading template: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Update { name }) => {
+            if let Err(e) = update_tmpls(name.as_deref()) {
+                eprintln!("Error updating templates: {}", e);
+                std::process::exit(1);
             }
         }
         Some(Commands::Remove { name }) => {
@@ -44,13 +131,48 @@ fn main() {
         Some(Commands::List {}) => {
             list_tmpls();
         }
+        Some(Commands::Search { query, json }) => {
+            if let Err(e) = search_tmpls(query.as_deref().unwrap_or(""), *json) {
+                eprintln!("Error searching templates: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Info { name, json }) => {
+            if let Err(e) = show_tmpl_info(name, *json) {
+                eprintln!("Error looking up template: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Replay { file }) => {
+            if let Err(e) = replay_tmpl(file, &args) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
         None => {
             if let Some(tmpl_name) = &args.tmpl {
-                if let Err(e) = use_tmpl(tmpl_name) {
-                    eprintln!("Error using template '{}': {}", tmpl_name, e);
-                } 
+                let answers = match Answers::new(&args.sets, args.answers.as_deref(), args.non_interactive) {
+                    Ok(answers) => answers,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                };
+
+                let options = RunOptions {
+                    dry_run: args.dry_run,
+                    error_policy: if args.keep_going { ErrorPolicy::KeepGoing } else { ErrorPolicy::Abort },
+                    rollback: !args.no_rollback,
+                    answers,
+                };
+
+                if let Err(e) = use_tmpl(tmpl_name, &options) {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
             } else {
                 eprintln!("No template name provided. Use `tmpl <name>` or `tmpl install <name>`.");
+                std::process::exit(1);
             }
         }
     }  
@@ -66,7 +188,11 @@ fn list_tmpls() {
             println!("Installed templates:");
             for entry in entries.flatten() {
                 if let Some(name) = entry.file_name().to_str() {
-                    println!(" - {}", name);
+                    match Manifest::load(&entry.path()) {
+                        Ok(Some(manifest)) => println!(" - {} {}", name, manifest.summary()),
+                        Ok(None) => println!(" - {}", name),
+                        Err(e) => println!(" - {} \x1b[33m({})\x1b[0m", name, e),
+                    }
                 }
             }
         } else {
@@ -75,6 +201,127 @@ fn list_tmpls() {
     }
 }
 
+fn search_tmpls(query: &str, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let results = Registry::new()?.search(query)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+    if results.is_empty() {
+        println!("No templates found matching '{}'", query);
+        return Ok(());
+    }
+
+    let several_registries = results.iter().any(|summary| summary.registry != results[0].registry);
+    let mut rows = vec![vec!["NAME", "VERSION", "DESCRIPTION", "TAGS"].into_iter().map(str::to_string).collect::<Vec<_>>()];
+    if several_registries {
+        rows[0].push("REGISTRY".to_string());
+    }
+    for summary in &results {
+        let mut row = vec![
+            summary.name.clone(),
+            summary.versions.last().map_or(String::new(), ToString::to_string),
+            truncate(summary.description.as_deref().unwrap_or(""), 60),
+            summary.tags.join(", "),
+        ];
+        if several_registries {
+            row.push(summary.registry.clone());
+        }
+        rows.push(row);
+    }
+    print_table(&rows);
+    Ok(())
+}
+
+/// Prints `rows` in aligned columns, the first row as a bold header.
+fn print_table(rows: &[Vec<String>]) {
+    let columns = rows[0].len();
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or(0))
+        .collect();
+
+    for (i, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        let line = cells.join("  ");
+        if i == 0 {
+            println!("\x1b[1m{}\x1b[0m", line.trim_end());
+        } else {
+            println!("{}", line.trim_end());
+        }
+    }
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        format!("{}…", text.chars().take(max - 1).collect::<String>())
+    }
+}
+
+fn show_tmpl_info(name: &str, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((summary, listing)) = Registry::new()?.info(name)? else {
+        return Err(format!("Template '{}' was not found in the registry", name).into());
+    };
+    let installed = Locked::installed(name).ok().map(|locked| locked.version);
+
+    if json {
+        let mut info = serde_json::to_value(&summary)?;
+        let manifest = listing.manifest.as_ref();
+        info["latest"] = serde_json::json!(listing.version);
+        info["license"] = serde_json::json!(manifest.and_then(|m| m.license.as_ref()));
+        info["dependencies"] = serde_json::json!(manifest.map(|m| {
+            m.dependencies
+                .iter()
+                .map(|(name, req)| (name.clone(), req.to_string()))
+                .collect::<std::collections::BTreeMap<_, _>>()
+        }));
+        info["installed"] = serde_json::json!(installed);
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    // The index describes the template as the registry lists it, the
+    // manifest fills in the rest.
+    let manifest = listing.manifest.as_ref();
+    let version = listing.version.as_ref().map_or(String::new(), |v| format!(" {}", v));
+    println!("\x1b[1m{}\x1b[0m{}", summary.name, version);
+    if let Some(description) = summary.description.as_ref().or(manifest.and_then(|m| m.description.as_ref())) {
+        println!("  {}", description);
+    }
+    if let Some(author) = summary.author.as_ref().or(manifest.and_then(|m| m.author.as_ref())) {
+        println!("  \x1b[90mauthor:\x1b[0m     {}", author);
+    }
+    if let Some(license) = manifest.and_then(|m| m.license.as_ref()) {
+        println!("  \x1b[90mlicense:\x1b[0m    {}", license);
+    }
+    if !summary.tags.is_empty() {
+        println!("  \x1b[90mtags:\x1b[0m       {}", summary.tags.join(", "));
+    }
+    if let Some(manifest) = manifest
+        && !manifest.dependencies.is_empty()
+    {
+        let dependencies: Vec<String> = manifest.dependencies.iter().map(|(name, req)| format!("{} {}", name, req)).collect();
+        println!("  \x1b[90mdepends on:\x1b[0m {}", dependencies.join(", "));
+    }
+    if !summary.versions.is_empty() {
+        let versions: Vec<String> = summary.versions.iter().map(ToString::to_string).collect();
+        println!("  \x1b[90mversions:\x1b[0m   {}", versions.join(", "));
+    }
+    println!("  \x1b[90mregistry:\x1b[0m   {}", summary.registry);
+    match installed {
+        Some(Some(version)) => println!("  \x1b[90minstalled:\x1b[0m  {}", version),
+        Some(None) => println!("  \x1b[90minstalled:\x1b[0m  yes"),
+        None => {}
+    }
+    Ok(())
+}
+
 fn download_tmpl(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     let name = name.trim();
 
@@ -82,70 +329,192 @@ fn download_tmpl(name: &str) -> Result<(), Box<dyn std::error::Error>> {
         return Err("Template name cannot be empty".into());
     }
 
-    if name.contains(' ') {
-        return Err("Template name cannot contain spaces".into());
-    }
-
     if name == "." {
         copy_current_dir_template()?;
         return Ok(());
     }
 
-    let url = format!(
-        "https://raw.githubusercontent.com/Jamie-Poeffel/tmpl/refs/heads/registry/{}/file.tmpl",
-        name
-    );
+    let (name, req) = match name.split_once('@') {
+        Some((name, version)) => (name, parse_version_req(version)?),
+        None => (name, VersionReq::STAR),
+    };
+    parse::validate_template_name(name)?;
+
+    let registry = Registry::new()?;
+    let Some(listing) = registry.lookup(name, &req)? else {
+        return Err(format!("Template '{}' was not found in the registry", name).into());
+    };
+    install_template(&registry, name, listing)?;
+    Ok(())
+}
+
+/// Reads the version after `name@`. A plain version means exactly that
+/// version; anything else is a range like `^1.2` or `>=1, <2`.
+fn parse_version_req(text: &str) -> Result<VersionReq, String> {
+    match Version::parse(text) {
+        Ok(version) => Ok(dependencies::exactly(&version)),
+        Err(_) => VersionReq::parse(text).map_err(|e| format!("Invalid version '{}': {}", text, e)),
+    }
+}
 
-    let client = Client::new();
-    let mut response = client.get(&url).send()?;
+/// Installs `listing` with its dependencies and returns the resolved graph.
+fn install_template(registry: &Registry, name: &str, listing: Listing) -> Result<Resolution, Box<dyn std::error::Error>> {
+    let resolution = dependencies::resolve(name, listing.manifest.as_ref(), &mut |dependency, req| {
+        registry.lookup(dependency, req).map_err(|e| e.to_string())
+    })?;
+    install_dependencies(registry, &resolution)?;
+    registry.download(name, &listing)?;
 
-    if !response.status().is_success() {
-        return Err(format!("Failed to download template '{}'", name).into());
+    if let Some(manifest) = &listing.manifest {
+        show_installed(manifest, &resolution);
     }
+    Ok(resolution)
+}
 
-    let total_size = response.content_length().unwrap_or(0);
-    let pb = ProgressBar::new(total_size);
+/// Installs exactly the templates pinned in the current directory's
+/// `tmpl.lock`, failing if the registry's content differs from the pin.
+fn install_locked() -> Result<(), Box<dyn std::error::Error>> {
+    let current_dir = std::env::current_dir()?;
+    let Some(lockfile) = Lockfile::load(&current_dir)? else {
+        return Err(format!("No template name given and no {} in the current directory", LOCK_FILE).into());
+    };
 
-    pb.set_style(
-        ProgressStyle::with_template(
-            "[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
-        )?
-        .progress_chars("#>-"),
-    );
+    let registry = Registry::new()?;
+    for (name, locked) in &lockfile.templates {
+        if Locked::installed(name).is_ok_and(|installed| installed == *locked) {
+            println!("\x1b[32m√\x1b[0m Template '{}' {} is installed", name, locked.describe());
+            continue;
+        }
 
-    let mut dest_path: PathBuf = dirs::data_dir()
-        .expect("Could not find data directory")
-        .join("tmpl/templates")
-        .join(name);
+        let req = locked.version.as_ref().map_or(VersionReq::STAR, dependencies::exactly);
+        let Some(mut listing) = registry.lookup(name, &req)? else {
+            return Err(format!("Template '{}' was not found in the registry", name).into());
+        };
+        listing.pin(&locked.hash);
+        registry.download(name, &listing)?;
+        println!("\x1b[32m√\x1b[0m Installed '{}' {}", name, locked.describe());
+    }
+    Ok(())
+}
 
-    std::fs::create_dir_all(&dest_path)?;
+/// Updates `name`, or every template installed from the registry, to the
+/// newest version and re-pins the updated templates in `tmpl.lock`.
+fn update_tmpls(name: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let names = match name {
+        Some(name) if !parse::template_dir(name).join("file.tmpl").is_file() => {
+            return Err(format!("Template '{}' is not installed", name).into());
+        }
+        Some(name) => vec![name.to_string()],
+        None => dependencies::installed_templates()
+            .into_iter()
+            .filter(|name| InstallRecord::load(name).is_none_or(|record| record.source.starts_with("registry")))
+            .collect(),
+    };
 
-    dest_path.push("file.tmpl");
+    let registry = Registry::new()?;
+    let mut updated = Vec::new();
+    for name in names {
+        let Some(listing) = registry.lookup(&name, &VersionReq::STAR)? else {
+            eprintln!("\x1b[33mwarning\x1b[0m: '{}' is not in the registry; skipping it", name);
+            continue;
+        };
+
+        let current = Locked::installed(&name)?.version;
+        if let (Some(current), Some(newest)) = (&current, &listing.version)
+            && newest <= current
+        {
+            println!("\x1b[32m√\x1b[0m Template '{}' {} is up to date", name, current);
+            updated.push(name);
+            continue;
+        }
 
-    let mut file = File::create(&dest_path)?;
-    let mut downloaded = 0u64;
-    let mut buffer = [0u8; 8192];
+        let resolution = install_template(&registry, &name, listing)?;
+        updated.extend(
+            resolution
+                .order
+                .into_iter()
+                .filter(|name| !matches!(resolution.nodes[name].origin, Origin::Installed)),
+        );
+    }
 
-    while let Ok(n) = response.read(&mut buffer) {
-        if n == 0 {
-            break;
+    let current_dir = std::env::current_dir()?;
+    if let Some(mut lockfile) = Lockfile::load(&current_dir)? {
+        let mut pinned = Vec::new();
+        for name in &updated {
+            if let Some(locked) = lockfile.templates.get(name)
+                && Locked::installed(name)? != *locked
+            {
+                pinned.push(name.as_str());
+            }
+        }
+        if !pinned.is_empty() {
+            lockfile.pin(pinned.iter().copied())?;
+            lockfile.save(&current_dir)?;
+            println!("\x1b[32m√\x1b[0m Updated {} in {}", pinned.join(", "), LOCK_FILE);
         }
-        file.write_all(&buffer[..n])?;
-        downloaded += n as u64;
-        pb.set_position(downloaded);
     }
+    Ok(())
+}
 
-    pb.finish_with_message(format!("\x1b[32m√\x1b[0m Template '{}' downloaded to {}", name, dest_path.display()));
+/// Downloads the dependencies in `resolution` that aren't installed yet,
+/// each before the templates that need it.
+fn install_dependencies(registry: &Registry, resolution: &Resolution) -> Result<(), Box<dyn std::error::Error>> {
+    for name in &resolution.order {
+        if let Origin::Registry(listing) = &resolution.nodes[name].origin {
+            registry.download(name, listing)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies the directory `from` with everything in it to `to`.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            std::fs::copy(entry.path(), dest)?;
+        }
+    }
     Ok(())
 }
 
+/// Shows what was installed and warns if this tmpl is too old to run it.
+fn show_installed(manifest: &Manifest, resolution: &Resolution) {
+    manifest.print_details();
+    if !manifest.dependencies.is_empty() {
+        println!("\nDependencies:");
+        resolution.print_tree();
+    }
+    if let Err(e) = manifest.check_compatible() {
+        eprintln!("\x1b[33mwarning\x1b[0m: {}", e);
+    }
+}
+
 fn remove_tmpl(name: &str) {
-    let mut tmpl_path: PathBuf = dirs::data_dir()
+    let tmpl_path: PathBuf = dirs::data_dir()
         .expect("Could not find data directory")
         .join("tmpl/templates")
         .join(name);
 
     if tmpl_path.exists() {
+        let dependents = dependencies::dependents(name);
+        if !dependents.is_empty() {
+            eprintln!(
+                "\x1b[33mwarning\x1b[0m: {} depend(s) on '{}' and will stop working without it",
+                dependents.join(", "),
+                name
+            );
+            let question = format!("Remove '{}' anyway?", name);
+            if !iostream::get_confirm(&question, false).unwrap_or(false) {
+                println!("Keeping template '{}'", name);
+                return;
+            }
+        }
+
         if let Err(e) = std::fs::remove_dir_all(&tmpl_path) {
             eprintln!("Error removing template '{}': {}", name, e);
         } else {
@@ -156,8 +525,29 @@ fn remove_tmpl(name: &str) {
     }
 }
 
-fn use_tmpl(template: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let _ = parse::parse_template(template)?;
+fn use_tmpl(template: &str, options: &RunOptions) -> Result<(), diagnostic::Failure> {
+    parse::parse_template(template, options)
+}
+
+fn replay_tmpl(path: &Path, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let record = AnswerRecord::load(path)?;
+
+    let hash = lock::tree_hash(&parse::template_dir(&record.template))
+        .map_err(|e| format!("Could not read template '{}': {}", record.template, e))?;
+    if hash != record.hash {
+        eprintln!(
+            "\x1b[33mwarning\x1b[0m: template '{}' has changed since the answers were recorded; the result may differ",
+            record.template
+        );
+    }
+
+    let options = RunOptions {
+        dry_run: args.dry_run,
+        error_policy: if args.keep_going { ErrorPolicy::KeepGoing } else { ErrorPolicy::Abort },
+        rollback: !args.no_rollback,
+        answers: Answers::new(&args.sets, args.answers.as_deref(), args.non_interactive)?.with_recorded(record.answers),
+    };
+    parse::parse_template(&record.template, &options)?;
     Ok(())
 }
 
@@ -203,25 +593,71 @@ fn copy_current_dir_template() -> Result<(), Box<dyn std::error::Error>> {
         &tmpl_files[0]
     };
     
+    // A manifest in the directory describes the template being copied.
+    let manifest = Manifest::load(&current_dir)?;
+
+    let selected_path = selected_file.path();
     let template_name = iostream::get_input_text(
         "Enter name for this template",
-        selected_file.path()
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("template")
+        match &manifest {
+            Some(manifest) => &manifest.name,
+            None => selected_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("template"),
+        }
     )?;
-
-    let mut dest_path: PathBuf = dirs::data_dir()
-        .expect("Could not find data directory")
-        .join("tmpl/templates")
-        .join(&template_name);
-
-    std::fs::create_dir_all(&dest_path)?;
-
-    dest_path.push("file.tmpl");
-    std::fs::copy(selected_file.path(), &dest_path)?;
+    parse::validate_template_name(&template_name)?;
+
+    let registry = Registry::new()?;
+    let resolution = dependencies::resolve(&template_name, manifest.as_ref(), &mut |dependency, req| {
+        registry.lookup(dependency, req).map_err(|e| e.to_string())
+    })?;
+    install_dependencies(&registry, &resolution)?;
+
+    // The copy is put together next to the store, so a failure leaves the
+    // installed version as it was.
+    let staging = registry::staging_dir(&template_name)?;
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    let result = stage_current_dir(&staging, &selected_path, &current_dir, manifest.as_ref())
+        .and_then(|record| {
+            registry::install_staged(&template_name, &staging)?;
+            Ok(record)
+        });
+    if result.is_err() && staging.exists() {
+        let _ = std::fs::remove_dir_all(&staging);
+    }
+    let record = result?;
 
     println!("\x1b[32m√\x1b[0m Template copied as '{}'", template_name);
+    // The hash to publish in a registry's index.json.
+    println!("  \x1b[90mhash:\x1b[0m {}", record.hash);
+    if let Some(manifest) = manifest {
+        show_installed(&manifest, &resolution);
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Copies the template in `current_dir` with `script` as its `file.tmpl` to
+/// `staging` and records it.
+fn stage_current_dir(
+    staging: &Path,
+    script: &Path,
+    current_dir: &Path,
+    manifest: Option<&Manifest>,
+) -> Result<InstallRecord, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(staging)?;
+    std::fs::copy(script, staging.join("file.tmpl"))?;
+    if manifest.is_some() {
+        std::fs::copy(current_dir.join(MANIFEST_FILE), staging.join(MANIFEST_FILE))?;
+    }
+    if current_dir.join(FILES_DIR).is_dir() {
+        copy_dir(&current_dir.join(FILES_DIR), &staging.join(FILES_DIR))?;
+    }
+
+    let source = current_dir.display().to_string();
+    Ok(InstallRecord::create(staging, manifest.map(|m| &m.version), &source)?)
+}
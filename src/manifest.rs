@@ -0,0 +1,511 @@
+use crate::ast::{Block, Expr, Parameter, Program, Prompt, PromptKind, StmtKind};
+use crate::diagnostic::{Diagnostic, Diagnostics, Severity};
+use crate::loader::Template;
+use crate::expr;
+use crate::interpreter;
+use crate::lexer::{self, is_identifier, Span, TokenKind};
+use crate::parse;
+use crate::prompt;
+use crate::value::Value;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// File next to `file.tmpl` describing the template.
+pub const MANIFEST_FILE: &str = "template.toml";
+
+/// The metadata in a template's `template.toml`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub name: String,
+    pub version: Version,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    /// The oldest tmpl that can run the template.
+    pub min_tmpl_version: Option<Version>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub parameters: Vec<ParameterSpec>,
+    /// Templates this one includes or imports, with the versions it works
+    /// with, installed along with it.
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, VersionReq>,
+}
+
+/// A `[[parameters]]` entry: a value the template is run with, asked for
+/// before the template itself runs.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParameterSpec {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: ParameterType,
+    /// The question asked, the name if there is none.
+    pub prompt: Option<String>,
+    pub default: Option<toml::Value>,
+    /// The choices of a `select` or `multiselect`.
+    #[serde(default)]
+    pub options: Vec<toml::Value>,
+    /// Rules written as in a `validate:` clause, e.g. `regex("^[a-z]+$")`.
+    #[serde(default)]
+    pub validate: Vec<String>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    #[default]
+    String,
+    Password,
+    Bool,
+    Number,
+    Select,
+    Multiselect,
+}
+
+impl Manifest {
+    /// Reads the manifest in `dir`, if the template has one.
+    pub fn load(dir: &Path) -> Result<Option<Self>, String> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read '{}': {}", path.display(), e))?;
+        Manifest::parse(&contents).map(Some)
+    }
+
+    /// Parses a manifest and checks that its parameters make sense, so a
+    /// broken one is caught when it is installed rather than when it's used.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let manifest: Manifest = toml::from_str(contents).map_err(|e| format!("Invalid {}: {}", MANIFEST_FILE, e))?;
+        manifest.parameters().map_err(|e| format!("Invalid {}: {}", MANIFEST_FILE, e))?;
+        for name in manifest.dependencies.keys() {
+            parse::validate_template_name(name).map_err(|e| format!("Invalid {}: {}", MANIFEST_FILE, e))?;
+        }
+        Ok(manifest)
+    }
+
+    /// Fails if the template needs a newer tmpl than this one.
+    pub fn check_compatible(&self) -> Result<(), String> {
+        let current = Version::parse(env!("CARGO_PKG_VERSION")).expect("package version is valid semver");
+        match &self.min_tmpl_version {
+            Some(min) if *min > current => Err(format!(
+                "Template '{}' needs tmpl {} or newer, but this is tmpl {}",
+                self.name, min, current
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The declared parameters as the prompts that ask for them.
+    pub fn parameters(&self) -> Result<Vec<Parameter>, String> {
+        let mut seen = HashSet::new();
+        self.parameters
+            .iter()
+            .map(|spec| {
+                if !seen.insert(spec.name.as_str()) {
+                    return Err(format!("parameter '{}' is declared twice", spec.name));
+                }
+                spec.to_parameter().map_err(|e| format!("parameter '{}': {}", spec.name, e))
+            })
+            .collect()
+    }
+
+    /// One line for `list`: the version, description and tags.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("\x1b[90m{}\x1b[0m", self.version);
+        if let Some(description) = &self.description {
+            summary.push_str(&format!(" - {}", description));
+        }
+        if !self.tags.is_empty() {
+            summary.push_str(&format!(" \x1b[90m[{}]\x1b[0m", self.tags.join(", ")));
+        }
+        summary
+    }
+
+    /// Prints the whole manifest after `install`.
+    pub fn print_details(&self) {
+        println!("\x1b[1m{}\x1b[0m {}", self.name, self.version);
+        if let Some(description) = &self.description {
+            println!("  {}", description);
+        }
+        if let Some(author) = &self.author {
+            println!("  \x1b[90mauthor:\x1b[0m  {}", author);
+        }
+        if let Some(license) = &self.license {
+            println!("  \x1b[90mlicense:\x1b[0m {}", license);
+        }
+        if let Some(min) = &self.min_tmpl_version {
+            println!("  \x1b[90mneeds:\x1b[0m   tmpl >= {}", min);
+        }
+        if !self.tags.is_empty() {
+            println!("  \x1b[90mtags:\x1b[0m    {}", self.tags.join(", "));
+        }
+        if !self.dependencies.is_empty() {
+            let dependencies: Vec<String> = self.dependencies.iter().map(|(name, req)| format!("{} {}", name, req)).collect();
+            println!("  \x1b[90mdepends on:\x1b[0m {}", dependencies.join(", "));
+        }
+        if !self.parameters.is_empty() {
+            println!("  \x1b[90mparameters:\x1b[0m");
+            for spec in &self.parameters {
+                let default = match &spec.default {
+                    Some(default) => format!(" (default: {})", text_of(default)),
+                    None => String::new(),
+                };
+                println!("    - {}: {}{}", spec.name, spec.kind.name(), default);
+            }
+        }
+    }
+}
+
+impl ParameterType {
+    fn name(self) -> &'static str {
+        match self {
+            ParameterType::String => "string",
+            ParameterType::Password => "password",
+            ParameterType::Bool => "bool",
+            ParameterType::Number => "number",
+            ParameterType::Select => "select",
+            ParameterType::Multiselect => "multiselect",
+        }
+    }
+}
+
+impl ParameterSpec {
+    fn to_parameter(&self) -> Result<Parameter, String> {
+        if !is_identifier(&self.name) {
+            return Err("names may only contain letters, digits and '_'".to_string());
+        }
+
+        let options: Vec<Value> = self.options.iter().map(value_of).collect();
+        let choices = || Expr::Literal(Value::List(options.clone()));
+        let kind = match self.kind {
+            ParameterType::String => PromptKind::Text,
+            ParameterType::Password => PromptKind::Password,
+            ParameterType::Bool => PromptKind::Confirm,
+            ParameterType::Number => PromptKind::Number,
+            ParameterType::Select => PromptKind::Select(choices()),
+            ParameterType::Multiselect => PromptKind::MultiSelect(choices()),
+        };
+
+        let has_options = matches!(self.kind, ParameterType::Select | ParameterType::Multiselect);
+        if has_options && options.is_empty() {
+            return Err(format!("a {} needs options", self.kind.name()));
+        }
+        if !has_options && !options.is_empty() {
+            return Err(format!("only a select or multiselect takes options, not a {}", self.kind.name()));
+        }
+
+        let default = self.default.as_ref().map(text_of).unwrap_or_default();
+        if !default.is_empty() && !default.contains('$') {
+            prompt::parse_answer(&kind, &default, &options).map_err(|e| format!("invalid default: {}", e))?;
+        }
+
+        let validators = self
+            .validate
+            .iter()
+            .map(|rule| parse::parse_validator(rule))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Parameter {
+            name: self.name.clone(),
+            prompt: Prompt {
+                kind,
+                question: self.prompt.clone().unwrap_or_else(|| self.name.clone()),
+                default,
+                validators,
+                when: None,
+            },
+        })
+    }
+}
+
+/// Compares the declared parameters with the variables the template uses.
+/// A parameter the template also sets is an error; parameters that are
+/// never used and variables that are neither declared nor set are warned
+/// about. Variables set and used by the templates it includes or imports
+/// count as well.
+pub fn check_parameters(template: &Template, dependencies: &[&Template]) -> Diagnostics {
+    let (program, source) = (&template.program, &template.source);
+    let mut diagnostics = Diagnostics::default();
+    let declared: HashSet<&str> = program.parameters.iter().map(|p| p.name.as_str()).collect();
+
+    let mut defined = Vec::new();
+    defined_by(program, &mut defined);
+    let mut defined_elsewhere = Vec::new();
+    for dependency in dependencies {
+        defined_by(&dependency.program, &mut defined_elsewhere);
+    }
+    let mut defined_elsewhere: HashSet<&str> = defined_elsewhere.into_iter().map(|(name, _)| name).collect();
+    for dependency in dependencies {
+        defined_elsewhere.extend(dependency.program.parameters.iter().map(|p| p.name.as_str()));
+    }
+
+    for (name, span) in &defined {
+        if declared.contains(name) {
+            diagnostics.push(source.error(
+                *span,
+                format!("'{}' is a parameter in {}; the template can't set it again", name, MANIFEST_FILE),
+            ));
+        }
+    }
+
+    let used = referenced_variables(&source.text);
+    let mut files_text = String::new();
+    for template in [template].iter().chain(dependencies) {
+        if let Some(files_dir) = &template.program.files_dir {
+            collect_text(files_dir, &mut files_text);
+        }
+    }
+    let mut used_elsewhere: HashSet<String> = variables_in(&files_text).into_iter().map(|(name, _)| name).collect();
+    for dependency in dependencies {
+        used_elsewhere.extend(referenced_variables(&dependency.source.text).into_iter().map(|(name, _)| name));
+    }
+
+    for parameter in &program.parameters {
+        let is_used = used.iter().any(|(name, _)| *name == parameter.name) || used_elsewhere.contains(&parameter.name);
+        if !is_used {
+            diagnostics.push(Diagnostic::template_warning(
+                &source.name,
+                format!("Parameter '{}' is declared in {} but never used", parameter.name, MANIFEST_FILE),
+            ));
+        }
+    }
+
+    let mut reported = HashSet::new();
+    for (name, span) in &used {
+        let name = name.as_str();
+        let known = declared.contains(name)
+            || defined_elsewhere.contains(name)
+            || defined.iter().any(|(d, _)| *d == name)
+            || name == "error";
+        if !known && reported.insert(name) {
+            diagnostics.push(source.diagnostic(
+                Severity::Warning,
+                *span,
+                format!("'${}' is neither a parameter in {} nor set by the template", name, MANIFEST_FILE),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// The variables set by `var:`, `for:` and function parameters anywhere in
+/// `program`.
+fn defined_by<'a>(program: &'a Program, defined: &mut Vec<(&'a str, Span)>) {
+    for function in program.functions.values() {
+        defined.extend(function.params.iter().map(|param| (param.as_str(), function.span)));
+        defined_in(&function.body, defined);
+    }
+    defined_in(&program.body, defined);
+}
+
+/// The variables set by `var:`, `for:` and function parameters in `block`.
+fn defined_in<'a>(block: &'a Block, defined: &mut Vec<(&'a str, Span)>) {
+    for stmt in block {
+        match &stmt.kind {
+            StmtKind::Var { name, .. } => defined.push((name, stmt.span)),
+            StmtKind::If { branches, otherwise } => {
+                for branch in branches {
+                    defined_in(&branch.body, defined);
+                }
+                if let Some(otherwise) = otherwise {
+                    defined_in(otherwise, defined);
+                }
+            }
+            StmtKind::For { var, body, .. } => {
+                defined.push((var, stmt.span));
+                defined_in(body, defined);
+            }
+            StmtKind::While { body, .. } => defined_in(body, defined),
+            StmtKind::Try { body, catch } => {
+                defined_in(body, defined);
+                if let Some(catch) = catch {
+                    defined_in(catch, defined);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Appends the names and text contents of everything in `dir`, so the
+/// variables used by `files/` count as used.
+fn collect_text(dir: &Path, text: &mut String) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        text.push_str(&entry.file_name().to_string_lossy());
+        text.push('\n');
+        let path = entry.path();
+        if path.is_dir() {
+            collect_text(&path, text);
+        } else if let Ok(contents) = fs::read_to_string(&path) {
+            text.push_str(&contents);
+            text.push('\n');
+        }
+    }
+}
+
+/// Finds the variables referenced in a template's script, with where they
+/// are. Only the text variables are substituted in is searched, so names in
+/// comments don't count.
+fn referenced_variables(script: &str) -> Vec<(String, Span)> {
+    let (tokens, _) = lexer::tokenize(script);
+    let mut found = Vec::new();
+    for token in &tokens {
+        let (text, start) = match &token.kind {
+            TokenKind::Text(text) | TokenKind::CommandLine(text) => (text, token.span),
+            // The body starts on the line after `<<EOF`.
+            TokenKind::Heredoc(text) => (text, Span { line: token.span.line + 1, column: 1 }),
+            _ => continue,
+        };
+        for (name, offset) in variables_in(text) {
+            found.push((name, position(text, start, offset)));
+        }
+    }
+    found
+}
+
+/// The variables `text` refers to as `$name` or in `${...}`, filter
+/// arguments included, with the byte offset of their `$`. `$$` escapes and
+/// the names of functions and filters are skipped.
+fn variables_in(text: &str) -> Vec<(String, usize)> {
+    let mut found = Vec::new();
+    let mut rest = text;
+
+    while let Some(dollar) = rest.find('$') {
+        let offset = text.len() - rest.len() + dollar;
+        let after = &rest[dollar + 1..];
+        if let Some(escaped) = after.strip_prefix('$') {
+            rest = escaped;
+            continue;
+        }
+
+        if let Some(inner) = after.strip_prefix('{') {
+            let Some(close) = interpreter::closing_brace(inner) else {
+                break;
+            };
+            // A broken expression is reported when the template runs.
+            if let Ok((expr, filters)) = expr::parse_embedded(&inner[..close]) {
+                let mut names = Vec::new();
+                expr_variables(&expr, &mut names);
+                for arg in filters.iter().flat_map(|filter| &filter.args) {
+                    expr_variables(arg, &mut names);
+                }
+                found.extend(names.into_iter().map(|name| (name, offset)));
+            }
+            rest = &inner[close + 1..];
+            continue;
+        }
+
+        let length = interpreter::name_length(after);
+        if length > 0 {
+            found.push((after[..length].to_string(), offset));
+        }
+        rest = &after[length..];
+    }
+    found
+}
+
+/// The variables `expr` reads.
+fn expr_variables(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Var(name) => names.push(name.clone()),
+        Expr::Str(text) => names.extend(variables_in(text).into_iter().map(|(name, _)| name)),
+        Expr::Literal(_) => {}
+        Expr::List(items) | Expr::Call(_, items) => {
+            for item in items {
+                expr_variables(item, names);
+            }
+        }
+        Expr::Map(entries) => {
+            for (_, value) in entries {
+                expr_variables(value, names);
+            }
+        }
+        Expr::Index(value, index) | Expr::Binary(_, value, index) => {
+            expr_variables(value, names);
+            expr_variables(index, names);
+        }
+        Expr::Field(value, _) | Expr::Not(value) => expr_variables(value, names),
+    }
+}
+
+/// Where the byte `offset` into `text` is, if `text` starts at `start`.
+fn position(text: &str, start: Span, offset: usize) -> Span {
+    let before = &text[..offset];
+    match before.rfind('\n') {
+        Some(newline) => Span {
+            line: start.line + before.matches('\n').count(),
+            column: offset - newline,
+        },
+        None => Span {
+            line: start.line,
+            column: start.column + offset,
+        },
+    }
+}
+
+/// A TOML value as a template value.
+fn value_of(value: &toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::Str(s.clone()),
+        toml::Value::Integer(n) => Value::Number(*n as f64),
+        toml::Value::Float(n) => Value::Number(*n),
+        toml::Value::Boolean(b) => Value::Bool(*b),
+        toml::Value::Array(items) => Value::List(items.iter().map(value_of).collect()),
+        other => Value::Str(other.to_string()),
+    }
+}
+
+/// A TOML default as the text it would have been typed as; lists become
+/// `a, b` for a multiselect.
+fn text_of(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Array(items) => items.iter().map(text_of).collect::<Vec<_>>().join(", "),
+        other => value_of(other).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(script: &str) -> Vec<String> {
+        referenced_variables(script).into_iter().map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn function_and_filter_names_are_not_variables() {
+        assert_eq!(
+            names("mkdir: ${len(items) | default(fallback)}/${ project | replace(\"-\", sep) | upper }\n"),
+            ["items", "fallback", "project", "sep"]
+        );
+    }
+
+    #[test]
+    fn comments_and_escapes_are_skipped() {
+        assert_eq!(names("# uses $old\n// and $older\nmkdir: $$HOME/$name\n"), ["name"]);
+    }
+
+    #[test]
+    fn heredocs_are_searched_with_their_own_lines() {
+        let script = "write_file(README.md): <<EOF\n# $title\n\nBy ${author | upper}\nEOF>>\n";
+        let found = referenced_variables(script);
+        let found: Vec<(&str, usize, usize)> = found
+            .iter()
+            .map(|(name, span)| (name.as_str(), span.line, span.column))
+            .collect();
+        assert_eq!(found, [("title", 2, 3), ("author", 4, 4)]);
+    }
+}
@@ -1,669 +1,932 @@
 use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
 use std::collections::HashMap;
-use std::process::Command;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
-use std::process::Stdio;
-use std::env;
+use crate::ast::{Block, Branch, CommandLine, Content, Expr, FunctionDefinition, Iterable, Program, Prompt, PromptKind, Stmt, StmtKind, Validator, VarValue};
+use crate::diagnostic::{Diagnostic, Diagnostics, Failure, Severity, Source};
+use crate::expr;
+use crate::executor::{FsExecutor, PlanExecutor};
+use crate::interpreter::{self, RunOptions};
 use crate::iostream;
-use std::fs;
-use dirs;
-use std::thread;
-
-pub struct FunctionDefinition {
-    pub start_line: usize,  
-    pub end_line: usize,    
-    pub params: Vec<String>, 
+use crate::journal::Journal;
+use crate::lexer::{self, is_identifier, Keyword, Span, Token, TokenKind};
+use crate::loader;
+use crate::lock;
+use crate::prompt;
+use crate::record::{AnswerRecord, RECORD_FILE};
+use regex::Regex;
+
+struct ParseError {
+    message: String,
+    span: Span,
 }
 
-fn parse_file(name: &str) -> io::Result<String> {
-    let mut path: PathBuf = dirs::data_dir()
+/// Directory in a template holding the files for `copy_tree:` and
+/// `render_tree:`.
+pub const FILES_DIR: &str = "files";
+
+/// Fails unless `name` can name an installed template. Names become
+/// directory names in the store, so they are a single path component of
+/// letters, digits, `-`, `_` and `.`, and not `.` or `..`.
+pub fn validate_template_name(name: &str) -> Result<(), String> {
+    let single = matches!(Path::new(name).components().collect::<Vec<_>>()[..], [Component::Normal(_)]);
+    let allowed = name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !single || !allowed || name == "." || name == ".." {
+        return Err(format!(
+            "Invalid template name '{}': use only letters, digits, '-', '_' and '.'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The directory an installed template lives in.
+pub fn template_dir(name: &str) -> PathBuf {
+    dirs::data_dir()
         .expect("Could not find data directory")
-        .join("tmpl/templates");
-    path.push(format!("{}/file.tmpl", name));
+        .join("tmpl/templates")
+        .join(name)
+}
+
+pub fn parse_file(name: &str) -> io::Result<String> {
+    let path = template_dir(name).join("file.tmpl");
 
     let mut file = File::open(&path)?;
 
     let mut contents = String::new();
-    file.read_to_string(&mut contents)?; 
+    file.read_to_string(&mut contents)?;
 
     Ok(contents)
 }
 
-pub fn parse_template(template: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut current_path = std::env::current_dir()?;
-    let mut variables: HashMap<String, String> = HashMap::new();
-    let mut functions: HashMap<String, FunctionDefinition> = HashMap::new();
-    let file_contents = parse_file(template)?;
-    let lines: Vec<_> = file_contents.lines().collect();   
-
-    let mut i = 0;
-    while i < lines.len() {
-        let line = lines[i].trim();
-        
-        if line.starts_with("function:") {
-            let (func_name, func_def, end_index) = parse_function_definition(&lines, i);
-            functions.insert(func_name, func_def);
-            i = end_index + 1;
-        } else {
-            i += 1;
-        }
-    }
-
-    let mut i = 0;
-    let mut is_command = false;
-
-    while i < lines.len() {
-        let line = lines[i].trim();
-        
-        if line.starts_with("function:") {
-            let mut brace_line = i;
-            if !line.ends_with('{') {
-                brace_line += 1;
-                while brace_line < lines.len() && lines[brace_line].trim() != "{" {
-                    brace_line += 1;
-                }
-            }
-            
-            let mut depth = 1;
-            i = brace_line + 1;
-            while i < lines.len() && depth > 0 {
-                let trimmed = lines[i].trim();
-                if trimmed == "{" {
-                    depth += 1;
-                } else if trimmed == "}" {
-                    depth -= 1;
-                }
-                i += 1;
-            }
-            continue;
-        }
-
-        let (new_is_command, skip_lines) = parse_line_and_execute(
-            lines[i], 
-            is_command, 
-            &mut variables,
-            &functions,
-            &lines,
-            i
-        );
-        is_command = new_is_command;
-        i += skip_lines + 1;
-    }
-
-    return Ok(())
+/// Parses and runs an installed template. Nothing runs unless the whole
+/// template and every template it includes or imports parse; every problem
+/// found is returned as a diagnostic. After a successful run the prompt
+/// answers are recorded in `.tmpl-answers.json`.
+pub fn parse_template(template: &str, options: &RunOptions) -> Result<(), Failure> {
+    run_template(template, options).map_err(|diagnostics| Failure {
+        template: template.to_string(),
+        diagnostics,
+    })
 }
 
-fn parse_line_and_execute(
-    line: &str,
-    is_command: bool,
-    variables: &mut HashMap<String, String>,
-    functions: &HashMap<String, FunctionDefinition>,
-    all_lines: &[&str],
-    current_index: usize,
-) -> (bool, usize) {
-    let line = line.trim();
-
-    if line.starts_with("var:") {
-        handle_var(line, variables);
-
-    } else if line.starts_with("mkdir:") {
-        handle_mkdir(line, variables);
-
-    } else if line.starts_with("create_file:") {
-        handle_create_file(line, variables);
-
-    } else if is_function_call(line, functions) {
-        handle_function_call(line, variables, functions, all_lines);
-
-    } else if line.starts_with("write_file(") {
-        let skip = handle_write_file(line, variables, all_lines, current_index);
-        return (is_command, skip);
+fn run_template(template: &str, options: &RunOptions) -> Result<(), Diagnostics> {
+    let (templates, warnings) = loader::load(template)?;
+    if !warnings.is_empty() {
+        eprint!("{}", warnings);
+    }
 
-    } else if line.starts_with("command") {
-        return (true, 0);
+    let start_dir = std::env::current_dir()
+        .map_err(|e| Diagnostic::template_error(template, format!("Could not read current directory: {}", e)))?;
+    lock::check(&start_dir, &templates)?;
 
-    } else if line.starts_with("end_command") {
-        return (false, 0);
+    if options.dry_run {
+        let mut plan = PlanExecutor::new(start_dir);
+        let result = interpreter::run(&templates, &mut plan, options);
+        plan.print();
+        result.map(|_| ())
+    } else {
+        let journal = if options.rollback {
+            let journal = Journal::new()
+                .map_err(|e| Diagnostic::template_error(template, format!("Could not start journal: {}", e)))?;
+            Some(journal)
+        } else {
+            None
+        };
 
-    } else if line.starts_with("-") {
-        handle_command_line(line, is_command, variables);
-        return (true, 0);
+        let mut executor = FsExecutor { journal };
+        let result = interpreter::run(&templates, &mut executor, options);
 
-    } else if line.starts_with("cd:") {
-        handle_cd(line, variables);
+        if let (Err(diagnostics), Some(journal)) = (&result, &mut executor.journal) {
+            offer_rollback(journal, diagnostics, options.answers.non_interactive);
+        }
 
-    } else if line.starts_with("if:") {
-        let skip = handle_if(line, is_command, variables, all_lines, current_index);
-        return (is_command, skip);
+        let answers = result?;
+        let saved = lock::tree_hash(&template_dir(template))
+            .map_err(|e| format!("Could not hash template '{}': {}", template, e))
+            .and_then(|hash| {
+                let record = AnswerRecord { template: template.to_string(), hash, answers };
+                record.save(&start_dir.join(RECORD_FILE))
+            });
+        if let Err(e) = saved {
+            eprintln!("Could not record answers: {}", e);
+        }
+        if let Err(e) = lock::record(&start_dir, &templates) {
+            eprintln!("Could not update {}: {}", lock::LOCK_FILE, e);
+        }
 
-    } else if line.starts_with("#")
-        || line.is_empty()
-        || line == "{"
-        || line == "}"
-    {
-    } else {
-        println!("Unknown command: {}", line);
+        Ok(())
     }
-
-    (is_command, 0)
 }
 
-fn replace_variables(text: &str, variables: &HashMap<String, String>) -> String {
-    let mut result = text.to_string();
-    
-    for (key, value) in variables {
-        let placeholder = format!("${}", key);
-        result = result.replace(&placeholder, value);
+/// Asks whether to undo the changes of a failed run and does so if confirmed.
+/// Without a terminal to ask on, changes are rolled back.
+fn offer_rollback(journal: &mut Journal, diagnostics: &Diagnostics, non_interactive: bool) {
+    if journal.is_empty() {
+        return;
+    }
 
-        result = result.replace(&format!("$${}", key), &format!("${}", key));
+    if let Some(first) = diagnostics.0.iter().find(|d| d.severity == Severity::Error) {
+        eprintln!("\x1b[31m×\x1b[0m {}", first.message);
     }
-    
-    result
-}
 
-fn parse_function_definition(
-    lines: &[&str], 
-    start_index: usize
-) -> (String, FunctionDefinition, usize) {
-    let line = lines[start_index].trim();
-    
-    let func_decl = line[9..].trim(); 
-    
-    let has_brace_on_same_line = func_decl.ends_with('{');
-    
-    let func_decl = func_decl.trim_end_matches('{').trim();
-    
-    let (name, params) = if func_decl.contains('(') {
-        let paren_pos = func_decl.find('(').unwrap();
-        let name = func_decl[..paren_pos].trim().to_string();
-        
-        let params_end = func_decl.rfind(')').unwrap_or(func_decl.len());
-        let params_str = &func_decl[paren_pos + 1..params_end].trim();
-        
-        let params: Vec<String> = if params_str.is_empty() {
-            vec![]
-        } else {
-            params_str
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .filter(|s| !s.is_empty())
-                .collect()
-        };
-        
-        (name, params)
+    let question = format!(
+        "The template failed. Roll back the {} file(s) and director(ies) it created or overwrote?",
+        journal.len()
+    );
+    let answer = if non_interactive {
+        Ok(true)
     } else {
-        (func_decl.to_string(), vec![])
+        iostream::get_confirm(&question, true)
     };
 
-    let brace_line = if has_brace_on_same_line {
-        start_index
-    } else {
-        let mut line_idx = start_index + 1;
-        while line_idx < lines.len() {
-            let trimmed = lines[line_idx].trim();
-            if trimmed == "{" {
-                break;
-            } else if !trimmed.is_empty() && !trimmed.starts_with("//") {
-                eprintln!("Expected '{{' after function declaration for '{}'", name);
-                return (name, FunctionDefinition {
-                    start_line: start_index + 1,
-                    end_line: start_index + 1,
-                    params,
-                }, start_index);
+    match answer {
+        Ok(true) => {
+            let failures = journal.rollback();
+            if failures.is_empty() {
+                println!("\x1b[32m√\x1b[0m Rolled back all changes");
+            }
+            for (path, e) in failures {
+                eprintln!("Failed to roll back '{}': {}", path.display(), e);
             }
-            line_idx += 1;
-        }
-        
-        if line_idx >= lines.len() {
-            eprintln!("Missing opening '{{' for function '{}'", name);
-            return (name, FunctionDefinition {
-                start_line: start_index + 1,
-                end_line: start_index + 1,
-                params,
-            }, start_index);
         }
-        
-        line_idx
+        Ok(false) => println!("Keeping the partially generated files"),
+        Err(e) => eprintln!("Could not ask whether to roll back, keeping changes: {}", e),
+    }
+}
+
+/// Parses a whole template. On success the program is returned together with
+/// any warnings; if there is at least one error, all diagnostics are returned.
+pub fn parse_program(source: &Source) -> Result<(Program, Diagnostics), Diagnostics> {
+    let (tokens, lex_errors) = lexer::tokenize(&source.text);
+    let mut parser = Parser {
+        source,
+        tokens,
+        pos: 0,
+        functions: HashMap::new(),
+        imports: Vec::new(),
+        dependencies: Vec::new(),
+        diagnostics: Diagnostics::default(),
     };
 
-    let mut depth = 1;
-    let mut end_index = brace_line + 1;
-    
-    while end_index < lines.len() && depth > 0 {
-        let trimmed = lines[end_index].trim();
-        
-        if trimmed.is_empty() || trimmed.starts_with("//") {
-            end_index += 1;
-            continue;
-        }
-        
-        if trimmed == "{" {
-            depth += 1;
-        } else if trimmed == "}" {
-            depth -= 1;
-            if depth == 0 {
-                break;
-            }
-        }
-        
-        end_index += 1;
+    for error in lex_errors {
+        parser.diagnostics.push(source.error(error.span, error.message));
     }
 
-    if depth != 0 {
-        eprintln!("Missing closing '}}' for function '{}'", name);
+    let body = parser.parse_statements(None);
+    parser.diagnostics.0.sort_by_key(|d| d.span.map(|s| (s.line, s.column)));
+
+    if parser.diagnostics.error_count() > 0 {
+        return Err(parser.diagnostics);
     }
 
-    let func_def = FunctionDefinition {
-        start_line: brace_line + 1,  
-        end_line: end_index,         
-        params,
+    let program = Program {
+        functions: parser.functions,
+        body,
+        parameters: Vec::new(),
+        files_dir: None,
+        imports: parser.imports,
+        dependencies: parser.dependencies,
     };
-
-    (name, func_def, end_index)
+    Ok((program, parser.diagnostics))
 }
 
-fn is_function_call(line: &str, functions: &HashMap<String, FunctionDefinition>) -> bool {
-    if !line.contains('(') || !line.ends_with(')') {
-        return false;
-    }
-    
-    let name_part = line.split('(').next().unwrap();
-    
-    if name_part.is_empty() || name_part.contains(':') {
-        return false;
-    }
-    
-    let is_valid_identifier = name_part.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_')
-        && name_part.chars().all(|c| c.is_alphanumeric() || c == '_');
-    
-    is_valid_identifier && functions.contains_key(name_part)
+struct Parser<'a> {
+    source: &'a Source,
+    tokens: Vec<Token>,
+    pos: usize,
+    functions: HashMap<String, FunctionDefinition>,
+    imports: Vec<String>,
+    dependencies: Vec<(String, Span)>,
+    diagnostics: Diagnostics,
 }
 
-fn handle_function_call(
-    line: &str,
-    variables: &mut HashMap<String, String>,
-    functions: &HashMap<String, FunctionDefinition>,
-    all_lines: &[&str]
-) {
-    let parts: Vec<&str> = line.split('(').collect();
-    let func_name = parts[0].trim();
-    let args_str = parts[1].trim_end_matches(')').trim();
-
-    let args: Vec<String> = if args_str.is_empty() {
-        vec![]
-    } else {
-        args_str
-            .split(',')
-            .map(|s| {
-                let trimmed = s.trim();
-                replace_variables(trimmed, variables)
-            })
-            .collect()
-    };
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
 
-    let func_def = match functions.get(func_name) {
-        Some(def) => def,
-        None => {
-            eprintln!("Function '{}' not found", func_name);
-            return;
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
         }
-    };
-
-    if args.len() != func_def.params.len() {
-        eprintln!(
-            "Function '{}' expects {} parameter(s), but {} were provided",
-            func_name,
-            func_def.params.len(),
-            args.len()
-        );
-        return;
+        token
     }
 
-    let mut local_vars = variables.clone();
-    
-    for (param_name, arg_value) in func_def.params.iter().zip(args.iter()) {
-        local_vars.insert(param_name.clone(), arg_value.clone());
+    fn skip_newlines(&mut self) {
+        while self.peek().kind == TokenKind::Newline {
+            self.pos += 1;
+        }
     }
 
-    let mut i = func_def.start_line;
-    let mut is_command = false;
-    
-    while i < func_def.end_line {
-        let (new_is_command, skip_lines) = parse_line_and_execute(
-            all_lines[i],
-            is_command,
-            &mut local_vars,
-            functions, 
-            all_lines,
-            i
-        );
-        is_command = new_is_command;
-        i += skip_lines + 1;
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<Span, ParseError> {
+        let token = self.advance();
+        if token.kind == kind {
+            Ok(token.span)
+        } else {
+            Err(unexpected(&token, what))
+        }
     }
-}
 
-fn handle_function(
-    line: &str, 
-    is_command: bool, 
-    variables: &HashMap<String, String>,
-    functions: &HashMap<String, FunctionDefinition>,
-    all_lines: &[&str],
-    current_index: usize
-) -> usize {
-    let func_name = &line[9..].trim();
-
-    if *func_name == "example_function" {
-        println!("Executing example_function");
-    } else {
-        eprintln!("Unknown function: {}", func_name);
+    fn expect_ident(&mut self, what: &str) -> Result<String, ParseError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Ident(name) => Ok(name),
+            _ => Err(unexpected(&token, what)),
+        }
     }
 
-    0
-}
+    fn expect_text(&mut self, what: &str) -> Result<(String, Span), ParseError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Text(text) => Ok((text, token.span)),
+            _ => Err(unexpected(&token, what)),
+        }
+    }
 
-fn handle_if(
-    line: &str,
-    _is_command: bool,
-    variables: &HashMap<String, String>,
-    all_lines: &[&str],
-    current_index: usize,
-) -> usize {
-    let mut condition_part = line.trim_start_matches("if:").trim();
+    fn expect_line_end(&mut self) -> Result<(), ParseError> {
+        match self.peek().kind {
+            TokenKind::Newline => {
+                self.pos += 1;
+                Ok(())
+            }
+            TokenKind::Eof => Ok(()),
+            _ => Err(unexpected(self.peek(), "end of line")),
+        }
+    }
 
-    let has_inline_brace = condition_part.ends_with('{');
-    if has_inline_brace {
-        condition_part = condition_part[..condition_part.len() - 1].trim();
+    fn report(&mut self, error: ParseError) {
+        self.diagnostics.push(self.source.error(error.span, error.message));
     }
 
-    let condition = replace_variables(condition_part, variables);
+    /// Skips the rest of the current line after an error so parsing can
+    /// resume at the next statement.
+    fn synchronize(&mut self) {
+        loop {
+            match self.peek().kind {
+                TokenKind::Eof => return,
+                TokenKind::Newline => {
+                    self.pos += 1;
+                    return;
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
 
-    let parts: Vec<&str> = condition.splitn(2, "==").collect();
-    if parts.len() != 2 {
-        eprintln!("Invalid if condition: {}", line);
-        return 0;
+    /// Parses statements until the end of the file, or until the closing brace
+    /// of the block opened at `open` when one is given. Errors are reported
+    /// and skipped so the rest of the template is still checked.
+    fn parse_statements(&mut self, open: Option<Span>) -> Block {
+        let mut statements = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            match self.peek().kind {
+                TokenKind::Eof => {
+                    if let Some(span) = open {
+                        self.report(ParseError {
+                            message: "Missing closing '}' for block".to_string(),
+                            span,
+                        });
+                    }
+                    return statements;
+                }
+                TokenKind::RBrace => {
+                    self.pos += 1;
+                    if open.is_some() {
+                        return statements;
+                    }
+                    let span = self.tokens[self.pos - 1].span;
+                    self.report(ParseError {
+                        message: "Unexpected '}' without a matching '{'".to_string(),
+                        span,
+                    });
+                }
+                _ => match self.parse_statement() {
+                    Ok(Some(statement)) => statements.push(statement),
+                    Ok(None) => {}
+                    Err(e) => {
+                        self.report(e);
+                        self.synchronize();
+                    }
+                },
+            }
+        }
     }
 
-    let left = parts[0].trim();
-    let right = parts[1].trim();
-    let condition_true = left == right;
+    /// Parses the body of a block statement: either a braced block (with the
+    /// brace on the same or the next line) or a single statement.
+    fn parse_body(&mut self, allow_single: bool, owner: &str) -> Result<Block, ParseError> {
+        if self.peek().kind == TokenKind::LBrace {
+            let open = self.advance().span;
+            return Ok(self.parse_statements(Some(open)));
+        }
 
-    if condition_true {
-        return 0;
-    }
+        self.expect_line_end()?;
+        self.skip_newlines();
 
-    let mut index = current_index + 1;
+        if self.peek().kind == TokenKind::LBrace {
+            let open = self.advance().span;
+            return Ok(self.parse_statements(Some(open)));
+        }
 
-    if !has_inline_brace {
-        if index < all_lines.len() && all_lines[index].trim() == "{" {
-            index += 1;
-        } else {
-            return 1;
+        if !allow_single {
+            return Err(ParseError {
+                message: format!("Expected '{{' after {}", owner),
+                span: self.peek().span,
+            });
+        }
+
+        match self.peek().kind {
+            TokenKind::Eof | TokenKind::RBrace => Err(ParseError {
+                message: format!("Expected a statement after {}", owner),
+                span: self.peek().span,
+            }),
+            _ => Ok(self.parse_statement()?.into_iter().collect()),
         }
     }
 
-    let mut depth = 1;
-    while index < all_lines.len() {
-        let line = all_lines[index].trim();
+    /// Parses one statement. Function definitions are recorded on the parser
+    /// and yield `None`.
+    fn parse_statement(&mut self) -> Result<Option<Stmt>, ParseError> {
+        let token = self.advance();
+        let span = token.span;
+
+        let kind = match token.kind {
+            TokenKind::Keyword(Keyword::Var) => {
+                let name = self.expect_ident("a variable name")?;
+                let value = if self.peek().kind == TokenKind::Equals {
+                    self.pos += 1;
+                    let (raw, value_span) = self.expect_text("a value")?;
+                    parse_var_value(&raw, value_span)?
+                } else {
+                    VarValue::Text(String::new())
+                };
+                StmtKind::Var { name, value }
+            }
+            TokenKind::Keyword(Keyword::Mkdir) => StmtKind::Mkdir(self.expect_text("a directory name")?.0),
+            TokenKind::Keyword(Keyword::CreateFile) => StmtKind::CreateFile(self.expect_text("a file name")?.0),
+            TokenKind::Keyword(Keyword::Cd) => StmtKind::Cd(self.expect_text("a directory")?.0),
+            TokenKind::Keyword(keyword @ (Keyword::CopyTree | Keyword::RenderTree)) => {
+                let (text, _) = self.expect_text("a directory in files/")?;
+                let (source, target) = text.split_once("->").unwrap_or((&text, "."));
+                StmtKind::Tree {
+                    source: source.trim().to_string(),
+                    target: target.trim().to_string(),
+                    render: keyword == Keyword::RenderTree,
+                }
+            }
+            TokenKind::Keyword(Keyword::WriteFile) => {
+                self.expect(TokenKind::LParen, "'('")?;
+                let (path, _) = self.expect_text("a file name")?;
+                self.expect(TokenKind::RParen, "')'")?;
+                self.expect(TokenKind::Colon, "':'")?;
+
+                let token = self.advance();
+                let content = match token.kind {
+                    TokenKind::Text(text) => Content::Inline(text),
+                    TokenKind::Heredoc(text) => Content::Heredoc(text),
+                    _ => return Err(unexpected(&token, "file content")),
+                };
+                StmtKind::WriteFile { path, content }
+            }
+            TokenKind::Keyword(Keyword::Command) => {
+                self.expect_line_end()?;
+                let mut lines = Vec::new();
+                loop {
+                    let token = self.advance();
+                    match token.kind {
+                        TokenKind::CommandLine(command) if command.is_empty() => {
+                            self.diagnostics.push(self.source.diagnostic(
+                                Severity::Warning,
+                                token.span,
+                                "Empty command line is skipped",
+                            ));
+                        }
+                        TokenKind::CommandLine(command) => lines.push(CommandLine { command, span: token.span }),
+                        TokenKind::Keyword(Keyword::EndCommand) => break,
+                        TokenKind::Newline => {}
+                        _ => return Err(unexpected(&token, "'- <command>' or 'end_command'")),
+                    }
+                }
+                StmtKind::Command(lines)
+            }
+            TokenKind::Keyword(Keyword::If) => {
+                let mut branches = Vec::new();
+                let mut valid = true;
+                let mut otherwise = None;
+                let mut branch_span = span;
+                let mut owner = "if condition";
+
+                loop {
+                    let (raw, condition_span) = self.expect_text("a condition")?;
+                    // A bad condition still has a body; parse it so its braces
+                    // don't get reported as errors of their own.
+                    let condition = parse_condition(&raw, condition_span).map_err(|e| self.report(e));
+                    let body = self.parse_body(true, owner)?;
+                    match condition {
+                        Ok(condition) => branches.push(Branch { condition, body, span: branch_span }),
+                        Err(()) => valid = false,
+                    }
+
+                    let after_body = self.pos;
+                    self.skip_newlines();
+                    match self.peek().kind {
+                        TokenKind::Keyword(Keyword::ElseIf) => {
+                            branch_span = self.advance().span;
+                            owner = "else if condition";
+                        }
+                        TokenKind::Keyword(Keyword::Else) => {
+                            self.pos += 1;
+                            otherwise = Some(self.parse_body(true, "else")?);
+                            break;
+                        }
+                        _ => {
+                            self.pos = after_body;
+                            break;
+                        }
+                    }
+                }
+
+                return Ok(valid.then_some(Stmt { kind: StmtKind::If { branches, otherwise }, span }));
+            }
+            TokenKind::Keyword(Keyword::For) => {
+                let (raw, header_span) = self.expect_text("a loop header")?;
+                let header = parse_for_header(&raw, header_span).map_err(|e| self.report(e));
+                let body = self.parse_body(true, "for loop")?;
+                return Ok(header.ok().map(|(var, iterable)| Stmt { kind: StmtKind::For { var, iterable, body }, span }));
+            }
+            TokenKind::Keyword(Keyword::While) => {
+                let (raw, condition_span) = self.expect_text("a condition")?;
+                let condition = parse_condition(&raw, condition_span).map_err(|e| self.report(e));
+                let body = self.parse_body(true, "while condition")?;
+                return Ok(condition.ok().map(|condition| Stmt { kind: StmtKind::While { condition, body }, span }));
+            }
+            TokenKind::Keyword(keyword @ (Keyword::ElseIf | Keyword::Else)) => {
+                let word = if keyword == Keyword::Else { "else" } else { "else if" };
+                self.report(ParseError {
+                    message: format!("'{}' without a preceding 'if' block", word),
+                    span,
+                });
+                // Skip its body so the braces don't get reported as well.
+                if keyword == Keyword::ElseIf {
+                    self.expect_text("a condition")?;
+                }
+                self.parse_body(true, word)?;
+                return Ok(None);
+            }
+            TokenKind::Keyword(Keyword::Try) => {
+                let body = self.parse_body(false, "try")?;
+
+                let after_body = self.pos;
+                self.skip_newlines();
+                let catch = if self.peek().kind == TokenKind::Keyword(Keyword::Catch) {
+                    self.pos += 1;
+                    Some(self.parse_body(false, "catch")?)
+                } else {
+                    self.pos = after_body;
+                    None
+                };
+
+                return Ok(Some(Stmt { kind: StmtKind::Try { body, catch }, span }));
+            }
+            TokenKind::Keyword(Keyword::Catch) => {
+                return Err(ParseError {
+                    message: "'catch' without a preceding 'try' block".to_string(),
+                    span,
+                });
+            }
+            TokenKind::Keyword(Keyword::Function) => {
+                let name = self.expect_ident("a function name")?;
+                let mut params = Vec::new();
+                if self.peek().kind == TokenKind::LParen {
+                    self.pos += 1;
+                    while self.peek().kind != TokenKind::RParen {
+                        params.push(self.expect_ident("a parameter name")?);
+                        if self.peek().kind == TokenKind::Comma {
+                            self.pos += 1;
+                        }
+                    }
+                    self.pos += 1;
+                }
 
-        if line.ends_with('{') {
-            depth += 1;
-        } else if line == "}" {
-            depth -= 1;
-            if depth == 0 {
-                let skipped = index - current_index;
-                return skipped;
+                let body = self.parse_body(false, &format!("function declaration for '{}'", name))?;
+                if let Some(existing) = self.functions.get(&name) {
+                    return Err(ParseError {
+                        message: format!("Function '{}' is already defined on line {}", name, existing.span.line),
+                        span,
+                    });
+                }
+                self.functions.insert(name, FunctionDefinition { params, body, span });
+                return Ok(None);
             }
-        }
+            TokenKind::Keyword(keyword @ (Keyword::Include | Keyword::Import)) => {
+                let (text, text_span) = self.expect_text("a template name")?;
+                let name = text.trim().to_string();
+                if name.contains('$') {
+                    return Err(ParseError {
+                        message: "Template names can't contain variables; they are loaded before the template runs"
+                            .to_string(),
+                        span: text_span,
+                    });
+                }
+                if name.contains(char::is_whitespace) {
+                    return Err(ParseError {
+                        message: "Template names cannot contain spaces".to_string(),
+                        span: text_span,
+                    });
+                }
 
-        index += 1;
-    }
+                self.dependencies.push((name.clone(), span));
+                if keyword == Keyword::Import {
+                    self.expect_line_end()?;
+                    if !self.imports.contains(&name) {
+                        self.imports.push(name);
+                    }
+                    return Ok(None);
+                }
+                StmtKind::Include(name)
+            }
+            TokenKind::Ident(name) => {
+                self.expect(TokenKind::LParen, "'('")?;
+                let (raw, _) = self.expect_text("arguments")?;
+                self.expect(TokenKind::RParen, "')'")?;
+
+                let args = if raw.is_empty() {
+                    vec![]
+                } else {
+                    raw.split(',').map(|s| s.trim().to_string()).collect()
+                };
+                StmtKind::Call { name, args }
+            }
+            _ => return Err(unexpected(&token, "a statement")),
+        };
 
-    0
+        self.expect_line_end()?;
+        Ok(Some(Stmt { kind, span }))
+    }
 }
 
-
-fn handle_var(line: &str, variables: &mut HashMap<String, String>) {
-    let name_and_value = &line[4..];
-    
-    let parts: Vec<&str> = name_and_value.splitn(2, '=').collect();
-    let var_name = parts[0].trim();
-    let var_value = if parts.len() > 1 {
-        let raw_value = parts[1].trim();
-        if raw_value.starts_with("input(") {
-            let question_and_default = raw_value[6..raw_value.len()-1].to_string();
-            let parts: Vec<&str> = question_and_default.splitn(2, ',').collect();
-            let question = parts[0].trim();
-            let default_value = if parts.len() > 1 {
-                parts[1].trim()
-            } else {
-                ""
-            };
-
-            let input = iostream::get_input_text(&question, default_value).unwrap();
-            input
-        } else {
-            replace_variables(raw_value, variables)
-        }
-    } else {
-        String::new()
+/// Prompt built-ins with the least and most arguments they take.
+const PROMPTS: &[(&str, usize, usize)] = &[
+    ("input", 1, 2),
+    ("password", 1, 1),
+    ("confirm", 1, 2),
+    ("number", 1, 2),
+    ("select", 2, 3),
+    ("multiselect", 2, 3),
+];
+
+fn parse_var_value(raw: &str, span: Span) -> Result<VarValue, ParseError> {
+    let Some((name, args, min, max)) = PROMPTS.iter().find_map(|&(name, min, max)| {
+        raw.strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('('))
+            .map(|args| (name, args, min, max))
+    }) else {
+        return Ok(VarValue::Text(raw.to_string()));
     };
-    
-    variables.insert(var_name.to_string(), var_value);
-}
 
-fn handle_mkdir(line: &str, variables: &HashMap<String, String>) {
-    let name = &line[6..];
-    let name = replace_variables(name, variables);
-    let name = name.trim();
+    let error = |message: String| ParseError { message, span };
 
-    let running = Arc::new(AtomicBool::new(true));
-    let loader_flag = running.clone();
+    let Some(close) = closing_paren(args) else {
+        return Err(error(format!("Missing ')' for {}()", name)));
+    };
+    let clauses_start = raw.len() - args.len() + close + 1;
+    let args = &args[..close];
+
+    let mut validators = Vec::new();
+    let mut when = None;
+    for (keyword, offset, body) in split_clauses(&raw[clauses_start..]).map_err(error)? {
+        match keyword {
+            "validate:" => {
+                validators = split_args(body, usize::MAX)
+                    .into_iter()
+                    .map(parse_validator)
+                    .collect::<Result<_, _>>()
+                    .map_err(error)?;
+            }
+            _ => {
+                let column = span.column + clauses_start + offset;
+                when = Some(parse_condition(body, Span { line: span.line, column })?);
+            }
+        }
+    }
 
-    let loader = thread::spawn(move || {
-        iostream::show_loader("Creating directory \x1b[90m...\x1b[0m", loader_flag);
-    });
+    // `input` predates quoting, so everything after its first comma is the
+    // default, commas included.
+    let args = split_args(args, if name == "input" { 2 } else { usize::MAX });
 
-    if let Err(e) = fs::create_dir_all(&name) {
-        eprintln!("Failed to create directory '{}': {}", name, e);
+    if args.len() < min || args.len() > max {
+        let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+        return Err(error(format!("{}() expects {} argument(s), but {} were provided", name, expected, args.len())));
     }
 
-    running.store(false, Ordering::Relaxed);
-    loader.join().unwrap();
-}
+    let question = unquote(args[0]).to_string();
+    let options = |text: &str| {
+        expr::parse(text).map_err(|e| error(format!("Invalid options for {}(): {}", name, e.message)))
+    };
 
-fn handle_create_file(line: &str, variables: &HashMap<String, String>) {
-    let name = &line[12..];
-    let name = replace_variables(name, variables);
-    let name = name.trim();
+    let (kind, default) = match name {
+        "password" => (PromptKind::Password, None),
+        "confirm" => (PromptKind::Confirm, args.get(1)),
+        "number" => (PromptKind::Number, args.get(1)),
+        "select" => (PromptKind::Select(options(args[1])?), args.get(2)),
+        "multiselect" => (PromptKind::MultiSelect(options(args[1])?), args.get(2)),
+        _ => (PromptKind::Text, args.get(1)),
+    };
+    let default = default.map_or("", |d| unquote(d)).to_string();
+
+    // Catch defaults that can never work while the template is parsed.
+    if !default.contains('$') {
+        let invalid = match kind {
+            PromptKind::Confirm if !default.is_empty() => prompt::parse_bool(&default).err(),
+            PromptKind::Number if !default.is_empty() => prompt::parse_number(&default).err(),
+            _ => None,
+        };
+        if let Some(message) = invalid {
+            return Err(error(format!("Invalid default for {}(): {}", name, message)));
+        }
+    }
 
-    let running = Arc::new(AtomicBool::new(true));
-    let loader_flag = running.clone();
+    Ok(VarValue::Prompt(Prompt { kind, question, default, validators, when }))
+}
 
-    let loader = thread::spawn(move || {
-        iostream::show_loader("Creating file \x1b[90m...\x1b[0m", loader_flag);
-    });
+/// Splits the text after a prompt into its `validate:` and `when:` clauses,
+/// returning each keyword with its body and the byte offset the body
+/// starts at.
+fn split_clauses(text: &str) -> Result<Vec<(&'static str, usize, &str)>, String> {
+    const CLAUSES: [&str; 2] = ["validate:", "when:"];
+
+    let mut starts = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut previous = ' ';
+
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                _ if depth == 0 && previous.is_whitespace() => {
+                    if let Some(keyword) = CLAUSES.iter().find(|keyword| text[i..].starts_with(*keyword)) {
+                        starts.push((*keyword, i));
+                    }
+                }
+                _ => {}
+            },
+        }
+        previous = c;
+    }
 
-    if let Err(e) = File::create(&name) {
-        eprintln!("Failed to create file '{}': {}", name, e);
+    let leading = &text[..starts.first().map_or(text.len(), |&(_, i)| i)];
+    if !leading.trim().is_empty() {
+        return Err(format!("Unexpected '{}', expected 'validate:' or 'when:'", leading.trim()));
     }
 
-    running.store(false, Ordering::Relaxed);
-    loader.join().unwrap();
+    let mut clauses = Vec::new();
+    for (n, &(keyword, start)) in starts.iter().enumerate() {
+        if starts[..n].iter().any(|&(seen, _)| seen == keyword) {
+            return Err(format!("'{}' is given more than once", keyword));
+        }
+        let end = starts.get(n + 1).map_or(text.len(), |&(_, i)| i);
+        let body = &text[start + keyword.len()..end];
+        let body_start = end - body.trim_start().len();
+        clauses.push((keyword, body_start, body.trim()));
+    }
+    Ok(clauses)
 }
 
-fn handle_write_file(
-    line: &str,
-    variables: &HashMap<String, String>,
-    all_lines: &[&str],
-    current_index: usize,
-) -> usize {
-    let parts: Vec<&str> = line.splitn(2, "):").collect();
-    if parts.len() != 2 {
-        eprintln!("Invalid write_file syntax: {}", line);
-        return 0;
-    }
-
-    let file_name_raw = parts[0]
-        .trim_start_matches("write_file(")
-        .trim();
-
-    let file_name = replace_variables(file_name_raw, variables);
-    let rhs = replace_variables(parts[1].trim(), variables);
-
-    let mut content = String::new();
-    let mut consumed_lines = 0;
-
-    // ─────────────────────────────────────
-    // HEREDOC MODE
-    // ─────────────────────────────────────
-    if rhs == "<<EOF" {
-        let mut i = current_index + 1;
-        let mut found_eof = false;
-
-        while i < all_lines.len() {
-            let line = all_lines[i];
-
-            if line.trim() == "EOF>>" {
-                consumed_lines = i - current_index;
-                found_eof = true;
-                break;
-            }
-
-            content.push_str(line);
-            content.push('\n');
-            i += 1;
+/// Parses one rule of a `validate:` clause, such as `required` or
+/// `range(1024, 65535)`.
+pub fn parse_validator(text: &str) -> Result<Validator, String> {
+    let (name, args) = match text.find('(') {
+        Some(paren) if text.ends_with(')') => (&text[..paren], split_args(&text[paren + 1..text.len() - 1], usize::MAX)),
+        _ => (text, Vec::new()),
+    };
+    let args: Vec<&str> = args.into_iter().map(unquote).collect();
+
+    let count = |n: &str| n.parse::<usize>().map_err(|_| format!("'{}' is not a whole number", n));
+    let number = |n: &str| prompt::parse_number(n);
+
+    let validator = match (name.trim(), args.as_slice()) {
+        ("required", []) => Validator::Required,
+        ("regex", [pattern]) => Validator::Regex(
+            Regex::new(pattern).map_err(|e| format!("Invalid regex '{}': {}", pattern, e))?,
+        ),
+        ("min_len", [n]) => Validator::MinLen(count(n)?),
+        ("max_len", [n]) => Validator::MaxLen(count(n)?),
+        ("range", [min, max]) => Validator::Range(number(min)?, number(max)?),
+        ("one_of", items) if !items.is_empty() => Validator::OneOf(items.iter().map(|item| item.to_string()).collect()),
+        (name @ ("required" | "regex" | "min_len" | "max_len" | "range" | "one_of"), _) => {
+            return Err(format!("Wrong number of arguments for validator '{}'", name));
         }
+        (name, _) => return Err(format!("Unknown validator '{}'", name)),
+    };
+    Ok(validator)
+}
 
-        if !found_eof {
-            eprintln!(
-                "write_file heredoc started but EOF>> not found (starting at line {})",
-                current_index + 1
-            );
-            // Skip everything we consumed to avoid re-processing garbage
-            consumed_lines = i - current_index;
+/// Finds the `)` that closes a call whose arguments start `text`.
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote = None;
+
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' | '[' | '{' => depth += 1,
+                ')' if depth == 0 => return Some(i),
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            },
         }
-    } else {
-        // ─────────────────────────────────
-        // SINGLE-LINE MODE
-        // ─────────────────────────────────
-        content = rhs
-            .replace("\\n", "\n")
-            .replace("\\t", "\t")
-            .replace("\\r", "\r");
-    }
-
-    // ─────────────────────────────────────
-    // WRITE FILE
-    // ─────────────────────────────────────
-    let running = Arc::new(AtomicBool::new(true));
-    let loader_flag = running.clone();
-
-    let loader = thread::spawn(move || {
-        iostream::show_loader("Writing file \x1b[90m...\x1b[0m", loader_flag);
-    });
-
-    let result = (|| -> std::io::Result<()> {
-        let mut file = File::create(&file_name)?;
-        file.write_all(content.as_bytes())?;
-        Ok(())
-    })();
-
-    if let Err(e) = result {
-        eprintln!("Failed to write to file '{}': {}", file_name, e);
     }
+    None
+}
 
-    running.store(false, Ordering::Relaxed);
-    loader.join().unwrap();
+/// Splits `a, "b, c", [d, e]` at the commas that aren't inside quotes or
+/// brackets, into at most `limit` arguments.
+fn split_args(text: &str, limit: usize) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' | '(' => depth += 1,
+                ']' | '}' | ')' => depth = depth.saturating_sub(1),
+                ',' if depth == 0 && args.len() + 1 < limit => {
+                    args.push(text[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
+            },
+        }
+    }
 
-    consumed_lines
+    if !text.trim().is_empty() || !args.is_empty() {
+        args.push(text[start..].trim());
+    }
+    args
 }
 
-
-fn handle_command_line(line: &str, is_command: bool, variables: &HashMap<String, String>) {
-    if !is_command {
-        println!("Error: Command line outside of command block: {}", line);
-        return;
+/// Strips one pair of matching quotes around `text`, if it has them.
+fn unquote(text: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = text.strip_prefix(quote).and_then(|t| t.strip_suffix(quote)) {
+            return inner;
+        }
     }
+    text
+}
 
-    let command = line[2..].trim();
-    let command = replace_variables(command, variables);
-    let value = command.clone();
+/// Parses `<name> in <values>`, where the values are a comma separated list,
+/// a range such as `1..5`, `glob(<pattern>)` or a list or map value.
+fn parse_for_header(raw: &str, span: Span) -> Result<(String, Iterable), ParseError> {
+    let Some((var, values)) = raw.split_once(" in ") else {
+        return Err(ParseError {
+            message: "Expected 'for: <name> in <values>'".to_string(),
+            span,
+        });
+    };
 
-    if command.is_empty() {
-        eprintln!("Empty command");
-        return;
+    let var = var.trim();
+    if !is_identifier(var) {
+        return Err(ParseError {
+            message: format!("Invalid loop variable name '{}'", var),
+            span,
+        });
     }
 
-    let args: Vec<&str> = command.split_whitespace().collect();
+    let values = values.trim();
+    let iterable = if values.starts_with('[') || (values.starts_with('$') && !values.contains(',')) {
+        let expr = expr::parse(values).map_err(|e| ParseError {
+            message: format!("Invalid loop values: {}", e.message),
+            span,
+        })?;
+        Iterable::Value(expr)
+    } else if let Some(pattern) = values.strip_prefix("glob(").and_then(|rest| rest.strip_suffix(')')) {
+        Iterable::Glob(pattern.trim().to_string())
+    } else if let Some((start, end)) = values.split_once("..").filter(|_| !values.contains(',')) {
+        Iterable::Range(start.trim().to_string(), end.trim().to_string())
+    } else if values.is_empty() {
+        return Err(ParseError {
+            message: "Missing values to loop over".to_string(),
+            span,
+        });
+    } else {
+        Iterable::List(values.split(',').map(|value| value.trim().to_string()).collect())
+    };
 
-    let running = Arc::new(AtomicBool::new(true));
-    let loader_flag = running.clone();
+    Ok((var.to_string(), iterable))
+}
 
-    let loader = thread::spawn(move || {
-        iostream::show_loader(&format!("Running command {} \x1b[90m...\x1b[0m", value), loader_flag);
-    });
+fn parse_condition(raw: &str, span: Span) -> Result<Expr, ParseError> {
+    if raw.is_empty() {
+        return Err(ParseError {
+            message: "Missing condition".to_string(),
+            span,
+        });
+    }
 
-    let result = {
-        #[cfg(target_os = "windows")]
-        {
-            Command::new("cmd")
-                .current_dir(std::env::current_dir().unwrap())
-                .args(&["/C", &command])
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .status()
-        }
+    expr::parse(raw).map_err(|e| ParseError {
+        message: format!("Invalid condition: {}", e.message),
+        span: Span {
+            line: span.line,
+            column: span.column + e.offset,
+        },
+    })
+}
 
-        #[cfg(not(target_os = "windows"))]
-        {
-            Command::new(args[0])
-                .current_dir(std::env::current_dir().unwrap())
-                .args(&args[1..])
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .status()
-        }
+fn unexpected(token: &Token, expected: &str) -> ParseError {
+    let found = match &token.kind {
+        TokenKind::Keyword(_) => "a keyword".to_string(),
+        TokenKind::Ident(name) => format!("'{}'", name),
+        TokenKind::Text(text) if text.is_empty() => "nothing".to_string(),
+        TokenKind::Text(text) => format!("'{}'", text),
+        TokenKind::Heredoc(_) => "a heredoc".to_string(),
+        TokenKind::CommandLine(_) => "a command line".to_string(),
+        TokenKind::LParen => "'('".to_string(),
+        TokenKind::RParen => "')'".to_string(),
+        TokenKind::LBrace => "'{'".to_string(),
+        TokenKind::RBrace => "'}'".to_string(),
+        TokenKind::Comma => "','".to_string(),
+        TokenKind::Colon => "':'".to_string(),
+        TokenKind::Equals => "'='".to_string(),
+        TokenKind::Newline => "end of line".to_string(),
+        TokenKind::Eof => "end of file".to_string(),
     };
 
-    match result {
-        Ok(status) => {
-            if !status.success() {
-                eprintln!("Command '{}' failed with exit code {:?}", command, status.code());
-            }
-        }
-        Err(e) => {
-            eprintln!("Failed to execute command '{}': {}", command, e);
-        }
+    ParseError {
+        message: format!("Expected {}, found {}", expected, found),
+        span: token.span,
     }
-
-    running.store(false, Ordering::Relaxed);
-    loader.join().unwrap();
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn handle_cd(line: &str, variables: &HashMap<String, String>) {
-    let dir = &line[3..];
-    let dir = replace_variables(dir, variables);
-    let dir = dir.trim();
-    let dir = dir.to_string();
-    let value = dir.clone();    
+    fn parse(text: &str) -> Result<Program, Diagnostics> {
+        parse_program(&Source::new("test", text.to_string())).map(|(program, _)| program)
+    }
 
-    let running = Arc::new(AtomicBool::new(true));
-    let loader_flag = running.clone();
+    #[test]
+    fn else_if_chains_into_one_statement() {
+        let program = parse(
+            "if: $a == x {\n    mkdir: a\n} else if: $a == y {\n    mkdir: b\n} else if: $b {\n    mkdir: c\n} else {\n    mkdir: d\n}\nmkdir: e\n",
+        )
+        .unwrap_or_else(|_| panic!("template should parse"));
 
-    let loader = thread::spawn(move || {
-        iostream::show_loader(&format!("Changing directory to '{}'", value), loader_flag);
-    });
+        assert_eq!(program.body.len(), 2);
+        let StmtKind::If { branches, otherwise } = &program.body[0].kind else {
+            panic!("expected an if statement");
+        };
+        let lines: Vec<usize> = branches.iter().map(|branch| branch.span.line).collect();
+        assert_eq!(lines, [1, 3, 5]);
+        assert!(branches.iter().all(|branch| branch.body.len() == 1));
+        assert_eq!(otherwise.as_ref().map(Vec::len), Some(1));
+    }
 
-    if let Err(e) = env::set_current_dir(&dir) {
-        eprintln!("Failed to change directory to '{}': {}", dir, e);
+    #[test]
+    fn every_error_is_reported_in_one_pass() {
+        let Err(diagnostics) = parse("mkdir: a\nnonsense\nif: {\n    mkdir: b\n}\nfor: x\nmkdir: c\n") else {
+            panic!("template should not parse");
+        };
+        let found: Vec<(usize, &str)> = diagnostics
+            .0
+            .iter()
+            .map(|d| (d.span.unwrap().line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (2, "Unknown command: nonsense"),
+                (3, "Missing condition"),
+                (6, "Expected 'for: <name> in <values>'"),
+            ]
+        );
     }
 
-    running.store(false, Ordering::Relaxed);
-    loader.join().unwrap();
+    #[test]
+    fn template_names_stay_inside_the_store() {
+        for name in ["api", "express-api", "my_template.v2"] {
+            assert!(validate_template_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", ".", "..", "../x", "a/b", "a\\b", "/abs", "a b", "ä"] {
+            assert!(validate_template_name(name).is_err(), "{}", name);
+        }
+    }
 }
@@ -0,0 +1,154 @@
+use crate::ast::{Prompt, PromptKind, Validator};
+use crate::iostream;
+use crate::value::Value;
+use std::rc::Rc;
+
+/// Reads `yes`, `no`, `y`, `n`, `true` and `false` in any case.
+pub fn parse_bool(text: &str) -> Result<bool, String> {
+    match text.trim().to_lowercase().as_str() {
+        "yes" | "y" | "true" => Ok(true),
+        "no" | "n" | "false" => Ok(false),
+        _ => Err(format!("'{}' is not yes or no", text)),
+    }
+}
+
+pub fn parse_number(text: &str) -> Result<f64, String> {
+    text.trim().parse().map_err(|_| format!("'{}' is not a number", text))
+}
+
+/// Turns an answer given as text, from `--set`, an answers file, the
+/// environment or the template's default, into the value the prompt would
+/// have produced.
+pub fn parse_answer(kind: &PromptKind, text: &str, options: &[Value]) -> Result<Value, String> {
+    match kind {
+        PromptKind::Text | PromptKind::Password => Ok(Value::Str(text.to_string())),
+        PromptKind::Confirm => Ok(Value::Bool(parse_bool(text)?)),
+        PromptKind::Number => Ok(Value::Number(parse_number(text)?)),
+        PromptKind::Select(_) => Ok(options[find_option(options, text)?].clone()),
+        PromptKind::MultiSelect(_) => split_list(text)
+            .into_iter()
+            .map(|item| Ok(options[find_option(options, item)?].clone()))
+            .collect::<Result<_, String>>()
+            .map(Value::List),
+    }
+}
+
+/// Checks `value` against every rule and returns the first failure.
+pub fn validate(validators: &[Validator], value: &Value) -> Result<(), String> {
+    for validator in validators {
+        let length = match value {
+            Value::List(items) => items.len(),
+            other => other.to_string().chars().count(),
+        };
+        let unit = if matches!(value, Value::List(_)) { "item(s)" } else { "character(s)" };
+
+        match validator {
+            Validator::Required if length == 0 => return Err("A value is required".to_string()),
+            Validator::Regex(regex) if !regex.is_match(&value.to_string()) => {
+                return Err(format!("Must match the pattern {}", regex.as_str()));
+            }
+            Validator::MinLen(min) if length < *min => return Err(format!("Must have at least {} {}", min, unit)),
+            Validator::MaxLen(max) if length > *max => return Err(format!("Must have at most {} {}", max, unit)),
+            Validator::Range(min, max) => {
+                let number = value.as_number()?;
+                if number < *min || number > *max {
+                    return Err(format!("Must be between {} and {}", min, max));
+                }
+            }
+            Validator::OneOf(allowed) => {
+                let items = match value {
+                    Value::List(items) => items.iter().map(Value::to_string).collect(),
+                    other => vec![other.to_string()],
+                };
+                if let Some(item) = items.iter().find(|item| !allowed.contains(item)) {
+                    return Err(format!("'{}' is not one of {}", item, allowed.join(", ")));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Asks the user for the value of `prompt`, with `default` preselected.
+/// Answers that fail the prompt's validators are rejected with the reason
+/// and asked for again.
+pub fn ask(prompt: &Prompt, question: &str, default: &str, options: &[Value]) -> Result<Value, String> {
+    let labels = || options.iter().map(Value::to_string).collect::<Vec<_>>();
+    let validators = || prompt.validators.clone();
+
+    let value = match &prompt.kind {
+        PromptKind::Text => {
+            let validators = validators();
+            let check = Rc::new(move |text: &str| validate(&validators, &Value::Str(text.to_string())));
+            Value::Str(iostream::get_checked_text(question, default, check).map_err(|e| e.to_string())?)
+        }
+        PromptKind::Password => {
+            let validators = validators();
+            let check = Rc::new(move |text: &str| validate(&validators, &Value::Str(text.to_string())));
+            Value::Str(iostream::get_password(question, check).map_err(|e| e.to_string())?)
+        }
+        PromptKind::Confirm => {
+            let default = if default.is_empty() { false } else { parse_bool(default)? };
+            Value::Bool(iostream::get_confirm(question, default).map_err(|e| e.to_string())?)
+        }
+        PromptKind::Number => {
+            let default = if default.is_empty() { None } else { Some(parse_number(default)?) };
+            let validators = validators();
+            let check = Rc::new(move |number: &f64| validate(&validators, &Value::Number(*number)));
+            Value::Number(iostream::get_number(question, default, check).map_err(|e| e.to_string())?)
+        }
+        PromptKind::Select(_) => {
+            // inquire's select has no validators, so check after the fact.
+            let mut default = if default.is_empty() { None } else { Some(find_option(options, default)?) };
+            loop {
+                let index = iostream::get_select(question, labels(), default).map_err(|e| e.to_string())?;
+                match validate(&prompt.validators, &options[index]) {
+                    Ok(()) => break options[index].clone(),
+                    Err(message) => {
+                        eprintln!("\x1b[31m×\x1b[0m {}", message);
+                        default = Some(index);
+                    }
+                }
+            }
+        }
+        PromptKind::MultiSelect(_) => {
+            let defaults = split_list(default)
+                .into_iter()
+                .map(|item| find_option(options, item))
+                .collect::<Result<Vec<_>, _>>()?;
+            let validators = validators();
+            let choices = options.to_vec();
+            let check = Rc::new(move |indices: &[usize]| {
+                validate(&validators, &Value::List(indices.iter().map(|&i| choices[i].clone()).collect()))
+            });
+            let indices = iostream::get_multiselect(question, labels(), &defaults, check).map_err(|e| e.to_string())?;
+            Value::List(indices.into_iter().map(|i| options[i].clone()).collect())
+        }
+    };
+
+    Ok(value)
+}
+
+fn find_option(options: &[Value], text: &str) -> Result<usize, String> {
+    options
+        .iter()
+        .position(|option| option.to_string() == text)
+        .ok_or_else(|| format!("'{}' is not one of {}", text, Value::List(options.to_vec())))
+}
+
+/// Splits a multi-select answer written as `a, b` or `["a", "b"]` into its
+/// items.
+fn split_list(text: &str) -> Vec<&str> {
+    let text = text.trim();
+    let inner = text
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(text);
+
+    inner
+        .split(',')
+        .map(|item| item.trim().trim_matches(['"', '\'']))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// File the answers of a run are written to, in the directory `tmpl` was
+/// started from.
+pub const RECORD_FILE: &str = ".tmpl-answers.json";
+
+/// The answers given to a template's prompts, pinned to the hash of the
+/// installed template they were given for so the run can be replayed.
+#[derive(Serialize, Deserialize)]
+pub struct AnswerRecord {
+    pub template: String,
+    pub hash: String,
+    pub answers: BTreeMap<String, String>,
+}
+
+impl AnswerRecord {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read '{}': {}", path.display(), e))?;
+        serde_json::from_str(&contents).map_err(|e| format!("Invalid answers record '{}': {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json + "\n").map_err(|e| format!("Could not write '{}': {}", path.display(), e))
+    }
+}
@@ -0,0 +1,899 @@
+use crate::config::Config;
+use crate::lexer::{self, Keyword, TokenKind};
+use crate::lock::{self, InstallRecord};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::parse::{self, FILES_DIR};
+use crate::source::{self, Download, RegistrySource};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, VerifyingKey};
+use indicatif::{ProgressBar, ProgressStyle};
+use semver::{Version, VersionReq};
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The versions of each template published in a registry, read from
+/// `index.json`. Each version lives in `<name>/<version>/`.
+#[derive(serde::Deserialize, Default)]
+struct Index(BTreeMap<String, IndexEntry>);
+
+#[derive(serde::Deserialize)]
+struct IndexEntry {
+    versions: BTreeMap<Version, Release>,
+    description: Option<String>,
+    author: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// What the index says about one version of a template.
+#[derive(serde::Deserialize, Clone)]
+struct Release {
+    /// The hash of the version's files, as `lock::tree_hash` computes it and
+    /// in the same form: `sha256:` and 64 lowercase hex digits. Any other
+    /// spelling is refused, so there is only one value to sign.
+    sha256: String,
+    /// A base64 ed25519 signature of `signed_message` for the version.
+    signature: Option<String>,
+}
+
+/// A version of a template a registry has, with its manifest if it has
+/// one.
+pub struct Listing {
+    /// `None` for a template without a manifest, which only has one version.
+    pub version: Option<Version>,
+    pub manifest: Option<Manifest>,
+    manifest_text: Option<String>,
+    /// The source that has it, as an index into `Registry::sources`.
+    source: usize,
+    /// The template's directory in the registry.
+    path: String,
+    /// `None` for a template that isn't in the index.
+    release: Option<Release>,
+    /// A hash the download must have besides the index's, e.g. from
+    /// `tmpl.lock`.
+    pinned_hash: Option<String>,
+}
+
+impl Listing {
+    /// Makes `Registry::download` refuse the template unless its content
+    /// has `hash`.
+    pub fn pin(&mut self, hash: &str) {
+        self.pinned_hash = Some(hash.to_string());
+    }
+}
+
+#[cfg(test)]
+impl Listing {
+    /// A listing for a template with `manifest` that isn't in any registry.
+    pub fn from_manifest(manifest: Manifest) -> Self {
+        Listing {
+            version: Some(manifest.version.clone()),
+            manifest: Some(manifest),
+            manifest_text: None,
+            source: 0,
+            path: String::new(),
+            release: None,
+            pinned_hash: None,
+        }
+    }
+}
+
+/// What a registry's index says about a template, for browsing.
+#[derive(serde::Serialize)]
+pub struct Summary {
+    pub name: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+    /// Oldest first.
+    pub versions: Vec<Version>,
+    pub registry: String,
+}
+
+impl Summary {
+    fn new(name: &str, entry: &IndexEntry, registry: &str) -> Self {
+        Summary {
+            name: name.to_string(),
+            description: entry.description.clone(),
+            author: entry.author.clone(),
+            tags: entry.tags.clone(),
+            versions: entry.versions.keys().cloned().collect(),
+            registry: registry.to_string(),
+        }
+    }
+
+    /// Whether every word of `query` is in the name, description, author or
+    /// tags, ignoring case.
+    fn matches(&self, query: &str) -> bool {
+        let fields: Vec<String> = [Some(&self.name), self.description.as_ref(), self.author.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(&self.tags)
+            .map(|field| field.to_lowercase())
+            .collect();
+        query
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| fields.iter().any(|field| field.contains(word)))
+    }
+}
+
+struct Source {
+    source: Box<dyn RegistrySource>,
+    index: OnceCell<Index>,
+}
+
+/// The configured registries, tried in order until one has the template
+/// asked for.
+pub struct Registry {
+    sources: Vec<Source>,
+    trusted_keys: Vec<VerifyingKey>,
+}
+
+impl Registry {
+    /// Sets up the registries from the config file, or the default registry
+    /// if none are configured.
+    pub fn new() -> Result<Self, String> {
+        let config = Config::load()?;
+        let sources = if config.registries.is_empty() {
+            vec![source::default_source()]
+        } else {
+            config
+                .registries
+                .iter()
+                .enumerate()
+                .map(|(i, registry)| source::from_config(registry).map_err(|e| format!("Registry {}: {}", i + 1, e)))
+                .collect::<Result<_, _>>()?
+        };
+
+        let trusted_keys = config
+            .trusted_keys
+            .iter()
+            .map(|key| parse_key(key).map_err(|e| format!("Invalid trusted key '{}': {}", key, e)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Registry::with_sources(sources, trusted_keys))
+    }
+
+    /// The registries `sources`, tried in order, only installing templates
+    /// signed with one of `trusted_keys` if there are any.
+    fn with_sources(sources: Vec<Box<dyn RegistrySource>>, trusted_keys: Vec<VerifyingKey>) -> Self {
+        Registry {
+            sources: sources
+                .into_iter()
+                .map(|source| Source { source, index: OnceCell::new() })
+                .collect(),
+            trusted_keys,
+        }
+    }
+
+    /// Looks up the newest version of a template matching `req` in the first
+    /// registry that has one, without downloading it. `None` means no
+    /// registry has the template.
+    pub fn lookup(&self, name: &str, req: &VersionReq) -> Result<Option<Listing>, Box<dyn Error>> {
+        let mut errors = Vec::new();
+        for i in 0..self.sources.len() {
+            match self.lookup_in(i, name, req) {
+                Ok(Some(listing)) => return Ok(Some(listing)),
+                Ok(None) => {}
+                Err(e) if self.sources.len() == 1 => errors.push(e.to_string()),
+                Err(e) => errors.push(format!("{}: {}", self.sources[i].source.name(), e)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(None)
+        } else {
+            Err(errors.join("\n").into())
+        }
+    }
+
+    /// The templates in the registries' indexes matching `query`, sorted by
+    /// name. A template in several registries is taken from the first, as
+    /// `tmpl install` would.
+    pub fn search(&self, query: &str) -> Result<Vec<Summary>, Box<dyn Error>> {
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for (i, source) in self.sources.iter().enumerate() {
+            let index = match self.index(i) {
+                Ok(index) => index,
+                Err(e) if self.sources.len() == 1 => return Err(e),
+                Err(e) => {
+                    eprintln!("\x1b[33mwarning\x1b[0m: {}: {}", source.source.name(), e);
+                    continue;
+                }
+            };
+            for (name, entry) in &index.0 {
+                if !seen.insert(name.as_str()) {
+                    continue;
+                }
+                let summary = Summary::new(name, entry, source.source.name());
+                if summary.matches(query) {
+                    found.push(summary);
+                }
+            }
+        }
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(found)
+    }
+
+    /// Looks up the newest version of `name` and what the index says about
+    /// it. Templates that aren't in the index are described by their
+    /// manifest.
+    pub fn info(&self, name: &str) -> Result<Option<(Summary, Listing)>, Box<dyn Error>> {
+        let Some(listing) = self.lookup(name, &VersionReq::STAR)? else {
+            return Ok(None);
+        };
+        let registry = self.sources[listing.source].source.name();
+
+        let summary = match self.index(listing.source)?.0.get(name) {
+            Some(entry) => Summary::new(name, entry, registry),
+            None => Summary {
+                name: name.to_string(),
+                description: listing.manifest.as_ref().and_then(|m| m.description.clone()),
+                author: listing.manifest.as_ref().and_then(|m| m.author.clone()),
+                tags: listing.manifest.as_ref().map(|m| m.tags.clone()).unwrap_or_default(),
+                versions: listing.version.iter().cloned().collect(),
+                registry: registry.to_string(),
+            },
+        };
+        Ok(Some((summary, listing)))
+    }
+
+    fn lookup_in(&self, source: usize, name: &str, req: &VersionReq) -> Result<Option<Listing>, Box<dyn Error>> {
+        if let Some(entry) = self.index(source)?.0.get(name) {
+            let Some((version, release)) = entry.versions.iter().rfind(|(v, _)| req.matches(v)) else {
+                let available: Vec<String> = entry.versions.keys().map(Version::to_string).collect();
+                return Err(format!(
+                    "Template '{}' has no version matching {} (available: {})",
+                    name,
+                    req,
+                    available.join(", ")
+                )
+                .into());
+            };
+            let path = format!("{}/{}", name, version);
+            let Some(mut listing) = self.fetch_listing(source, name, &path)? else {
+                return Err(format!("Template '{}' {} is listed in the registry index but missing", name, version).into());
+            };
+            // The signature covers the index's version, so the files have to
+            // say the same.
+            if listing.version.as_ref() != Some(version) {
+                return Err(format!(
+                    "Template '{}' {} has a different version in its {}",
+                    name, version, MANIFEST_FILE
+                )
+                .into());
+            }
+            listing.release = Some(release.clone());
+            return Ok(Some(listing));
+        }
+
+        // Templates published before the index have a single, unversioned
+        // directory.
+        let Some(listing) = self.fetch_listing(source, name, name)? else {
+            return Ok(None);
+        };
+        match &listing.version {
+            Some(version) if !req.matches(version) => {
+                Err(format!("Template '{}' is only available as {}, which doesn't match {}", name, version, req).into())
+            }
+            None if *req != VersionReq::STAR => {
+                Err(format!("Template '{}' has no {} and so no version to match {}", name, MANIFEST_FILE, req).into())
+            }
+            _ => Ok(Some(listing)),
+        }
+    }
+
+    fn index(&self, source: usize) -> Result<&Index, Box<dyn Error>> {
+        let Source { source, index } = &self.sources[source];
+        if let Some(index) = index.get() {
+            return Ok(index);
+        }
+        let parsed: Index = match read_text(source.as_ref(), "index.json")? {
+            Some(text) => serde_json::from_str(&text).map_err(|e| format!("Invalid registry index: {}", e))?,
+            None => Index::default(),
+        };
+        for name in parsed.0.keys() {
+            parse::validate_template_name(name).map_err(|e| format!("Invalid registry index: {}", e))?;
+        }
+        Ok(index.get_or_init(|| parsed))
+    }
+
+    fn fetch_listing(&self, source: usize, name: &str, path: &str) -> Result<Option<Listing>, Box<dyn Error>> {
+        let registry = self.sources[source].source.as_ref();
+
+        if let Some(text) = read_text(registry, &format!("{}/{}", path, MANIFEST_FILE))? {
+            let manifest = Manifest::parse(&text).map_err(|e| format!("Template '{}': {}", name, e))?;
+            return Ok(Some(Listing {
+                version: Some(manifest.version.clone()),
+                manifest: Some(manifest),
+                manifest_text: Some(text),
+                source,
+                path: path.to_string(),
+                release: None,
+                pinned_hash: None,
+            }));
+        }
+
+        // The manifest is optional, so a template exists if its script does.
+        if !registry.exists(&format!("{}/file.tmpl", path))? {
+            return Ok(None);
+        }
+        Ok(Some(Listing {
+            version: None,
+            manifest: None,
+            manifest_text: None,
+            source,
+            path: path.to_string(),
+            release: None,
+            pinned_hash: None,
+        }))
+    }
+
+    /// Downloads a template that `lookup` found, verifies it and moves it
+    /// into the store, recording the installed version, the registry and
+    /// its hash. If anything fails, the store is left as it was.
+    pub fn download(&self, name: &str, listing: &Listing) -> Result<InstallRecord, Box<dyn Error>> {
+        // Everything is downloaded next to the store first, so nothing
+        // reaches it before it is complete and verified.
+        let staging = staging_dir(name)?;
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+
+        let result = self.stage(name, listing, &staging).and_then(|record| {
+            install_staged(name, &staging)?;
+            Ok(record)
+        });
+        if result.is_err() && staging.exists() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+
+        let record = result?;
+        println!("\x1b[32m√\x1b[0m Template '{}' downloaded to {}", name, parse::template_dir(name).display());
+        Ok(record)
+    }
+
+    /// Downloads and verifies a template in `staging`.
+    fn stage(&self, name: &str, listing: &Listing, staging: &Path) -> Result<InstallRecord, Box<dyn Error>> {
+        let registry = self.sources[listing.source].source.as_ref();
+        fs::create_dir_all(staging)?;
+
+        let path = format!("{}/file.tmpl", listing.path);
+        let Some(download) = registry.open(&path)? else {
+            return Err(format!("Failed to download template '{}'", name).into());
+        };
+
+        let total_size = download.len.unwrap_or(0);
+        let pb = ProgressBar::new(total_size);
+
+        pb.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+            )?
+            .progress_chars("#>-"),
+        );
+
+        fetch(download, &path, &staging.join("file.tmpl"), Some(&pb))?;
+        pb.finish_and_clear();
+
+        if let Some(text) = &listing.manifest_text {
+            fs::write(staging.join(MANIFEST_FILE), text)?;
+        }
+        let needs_files = uses_files_dir(&fs::read_to_string(staging.join("file.tmpl"))?);
+        download_files(registry, &listing.path, &staging.join(FILES_DIR), needs_files)?;
+
+        let hash = lock::tree_hash(staging)?;
+        self.verify(name, listing, &hash)?;
+
+        let record = InstallRecord {
+            version: listing.version.clone(),
+            source: format!("registry:{}", registry.name()),
+            hash,
+        };
+        record.save(staging)?;
+        Ok(record)
+    }
+
+    /// Checks the hash of a downloaded template against the index and any
+    /// pin, and its signature against the trusted keys if there are any.
+    fn verify(&self, name: &str, listing: &Listing, hash: &str) -> Result<(), String> {
+        let label = match &listing.version {
+            Some(version) => format!("'{}' {}", name, version),
+            None => format!("'{}'", name),
+        };
+
+        if let Some(pinned) = &listing.pinned_hash
+            && pinned != hash
+        {
+            return Err(format!(
+                "Template {} doesn't match the pinned hash {}; the registry's copy is {}. Nothing was installed",
+                label, pinned, hash
+            ));
+        }
+
+        let Some(release) = &listing.release else {
+            if !self.trusted_keys.is_empty() {
+                return Err(format!(
+                    "Template {} isn't in the registry index, so it has no signature to check against the trusted keys. Nothing was installed",
+                    label
+                ));
+            }
+            eprintln!(
+                "\x1b[33mwarning\x1b[0m: template {} isn't in the registry index, so its content can't be verified",
+                label
+            );
+            return Ok(());
+        };
+
+        if !is_canonical_hash(&release.sha256) {
+            return Err(format!(
+                "Template {} has the hash '{}' in the registry index, but hashes are written as `sha256:` and 64 lowercase hex digits. Nothing was installed",
+                label, release.sha256
+            ));
+        }
+        if release.sha256 != hash {
+            return Err(format!(
+                "Template {} doesn't match the registry index: expected {}, downloaded {}. Nothing was installed",
+                label, release.sha256, hash
+            ));
+        }
+
+        if self.trusted_keys.is_empty() {
+            return Ok(());
+        }
+        let Some(version) = &listing.version else {
+            return Err(format!("Template {} has no version to check its signature against. Nothing was installed", label));
+        };
+        let Some(signature) = &release.signature else {
+            return Err(format!("Template {} isn't signed, but trusted keys are configured. Nothing was installed", label));
+        };
+        let signature = BASE64
+            .decode(signature)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| Signature::from_slice(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Template {} has an invalid signature: {}. Nothing was installed", label, e))?;
+        let message = signed_message(name, version, &release.sha256);
+        if !self
+            .trusted_keys
+            .iter()
+            .any(|key| key.verify_strict(message.as_bytes(), &signature).is_ok())
+        {
+            return Err(format!(
+                "Template {} isn't signed by any of the trusted keys. Nothing was installed",
+                label
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Where a template is put together before it is moved into the store, on
+/// the same file system so the move is a rename.
+pub fn staging_dir(name: &str) -> Result<PathBuf, String> {
+    parse::validate_template_name(name)?;
+    Ok(dirs::data_dir()
+        .expect("Could not find data directory")
+        .join("tmpl/downloads")
+        .join(name))
+}
+
+/// Moves the template in `staging` into the store in place of `name`. The
+/// installed version is moved aside first and put back if the move fails,
+/// so the store never holds half a template.
+pub fn install_staged(name: &str, staging: &Path) -> io::Result<()> {
+    parse::validate_template_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let backup = dirs::data_dir()
+        .expect("Could not find data directory")
+        .join("tmpl/replaced")
+        .join(name);
+    replace_dir(staging, &parse::template_dir(name), &backup)
+}
+
+/// Moves `staging` to `dest`, keeping whatever was at `dest` in `backup`
+/// until the move has succeeded.
+fn replace_dir(staging: &Path, dest: &Path, backup: &Path) -> io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let had_previous = dest.exists();
+    if had_previous {
+        if backup.exists() {
+            fs::remove_dir_all(backup)?;
+        }
+        if let Some(parent) = backup.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(dest, backup)?;
+    }
+
+    if let Err(e) = fs::rename(staging, dest) {
+        if had_previous {
+            let _ = fs::rename(backup, dest);
+        }
+        return Err(e);
+    }
+    if had_previous {
+        let _ = fs::remove_dir_all(backup);
+    }
+    Ok(())
+}
+
+/// Writes a download to `dest` through a temporary file that is only
+/// renamed into place once it is complete.
+fn fetch(mut download: Download, path: &str, dest: &Path, pb: Option<&ProgressBar>) -> Result<(), Box<dyn Error>> {
+    let mut part = dest.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
+    let mut file = File::create(&part)?;
+    let mut downloaded = 0u64;
+    let mut buffer = [0u8; 8192];
+
+    loop {
+        let n = match download.reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("Downloading '{}' failed: {}", path, e).into()),
+        };
+        file.write_all(&buffer[..n])?;
+        downloaded += n as u64;
+        if let Some(pb) = pb {
+            pb.set_position(downloaded);
+        }
+    }
+    file.sync_all()?;
+    check_length(&download, path, downloaded)?;
+
+    fs::rename(&part, dest)?;
+    Ok(())
+}
+
+/// Fails if fewer or more bytes arrived than the source announced.
+fn check_length(download: &Download, path: &str, received: u64) -> Result<(), String> {
+    match download.len {
+        Some(len) if len != received => Err(format!(
+            "Downloading '{}' was cut off: received {} of {} bytes",
+            path, received, len
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// What a release's signature is made over: the template's name, version
+/// and hash, so a signed release can't be passed off as another template or
+/// version.
+fn signed_message(name: &str, version: &Version, sha256: &str) -> String {
+    format!("{}@{}\n{}", name, version, sha256)
+}
+
+/// Whether `hash` is written the way `lock::tree_hash` writes it.
+fn is_canonical_hash(hash: &str) -> bool {
+    hash.strip_prefix("sha256:")
+        .is_some_and(|digits| digits.len() == 64 && digits.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))
+}
+
+fn parse_key(key: &str) -> Result<VerifyingKey, String> {
+    let bytes = BASE64.decode(key.trim()).map_err(|e| e.to_string())?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| "an ed25519 public key has 32 bytes".to_string())?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| e.to_string())
+}
+
+/// Reads a whole text file from `registry`; `None` if it doesn't exist.
+fn read_text(registry: &dyn RegistrySource, path: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(mut download) = registry.open(path)? else {
+        return Ok(None);
+    };
+    let mut bytes = Vec::new();
+    download
+        .reader
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Downloading '{}' failed: {}", path, e))?;
+    check_length(&download, path, bytes.len() as u64)?;
+    Ok(Some(String::from_utf8(bytes).map_err(|_| format!("'{}' is not UTF-8 text", path))?))
+}
+
+/// Whether the template `script` copies from its `files/` directory.
+fn uses_files_dir(script: &str) -> bool {
+    let (tokens, _) = lexer::tokenize(script);
+    tokens
+        .iter()
+        .any(|token| matches!(token.kind, TokenKind::Keyword(Keyword::CopyTree | Keyword::RenderTree)))
+}
+
+/// Downloads the `files/` tree of the template at `path`, if it has one,
+/// into `dest`. Unless the template `needs` it, a registry that can't list
+/// the tree, say because its API is rate limited, counts as having none;
+/// the index's hash still catches files that went missing that way.
+fn download_files(registry: &dyn RegistrySource, path: &str, dest: &Path, needs: bool) -> Result<(), Box<dyn Error>> {
+    let prefix = format!("{}/{}", path, FILES_DIR);
+    let files = match registry.list(&prefix) {
+        Ok(files) => files,
+        Err(e) if !needs => {
+            eprintln!("\x1b[33mwarning\x1b[0m: could not list the files of '{}': {}", path, e);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} files")?.progress_chars("#>-"));
+
+    for file in files {
+        // The list comes from the registry, so a path must not be able to
+        // reach outside the template.
+        source::check_relative(&file)?;
+        let source_path = format!("{}/{}", prefix, file);
+        let Some(download) = registry.open(&source_path)? else {
+            return Err(format!("'{}' is listed in the registry but missing", source_path).into());
+        };
+
+        let path = dest.join(&file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fetch(download, &source_path, &path, None)?;
+        pb.inc(1);
+    }
+
+    pb.finish_and_clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::DirSource;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    /// Publishes `files` as `name` `version` in the registry at `root`, with
+    /// a manifest, and returns the hash of its content.
+    fn publish(root: &Path, name: &str, version: &str, files: &[(&str, &str)]) -> String {
+        let dir = root.join(name).join(version);
+        let manifest = format!("name = \"{}\"\nversion = \"{}\"\n", name, version);
+        for (path, contents) in [(MANIFEST_FILE, manifest.as_str())].iter().chain(files) {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        lock::tree_hash(&dir).unwrap()
+    }
+
+    fn write_index(root: &Path, index: serde_json::Value) {
+        fs::write(root.join("index.json"), index.to_string()).unwrap();
+    }
+
+    fn dir_registry(root: &Path, trusted_keys: Vec<VerifyingKey>) -> Registry {
+        Registry::with_sources(vec![Box::new(DirSource::new(root.to_path_buf()))], trusted_keys)
+    }
+
+    /// A registry whose files, and their listing, are given as they are.
+    struct MemorySource(HashMap<String, String>);
+
+    impl RegistrySource for MemorySource {
+        fn name(&self) -> &str {
+            "memory"
+        }
+
+        fn open(&self, path: &str) -> Result<Option<Download>, Box<dyn Error>> {
+            Ok(self.0.get(path).map(|contents| Download {
+                len: Some(contents.len() as u64),
+                reader: Box::new(io::Cursor::new(contents.clone().into_bytes())),
+            }))
+        }
+
+        fn list(&self, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+            let prefix = format!("{}/", path);
+            Ok(self
+                .0
+                .keys()
+                .filter_map(|key| key.strip_prefix(&prefix).map(str::to_string))
+                .collect())
+        }
+    }
+
+    /// A registry that serves files but, like a rate limited API, can't
+    /// list them.
+    struct UnlistableSource(MemorySource);
+
+    impl RegistrySource for UnlistableSource {
+        fn name(&self) -> &str {
+            "unlistable"
+        }
+
+        fn open(&self, path: &str) -> Result<Option<Download>, Box<dyn Error>> {
+            self.0.open(path)
+        }
+
+        fn list(&self, _path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+            Err("API rate limit exceeded".into())
+        }
+    }
+
+    #[test]
+    fn lookup_takes_the_newest_matching_version() {
+        let registry_dir = TempDir::new().unwrap();
+        let root = registry_dir.path();
+        let mut versions = serde_json::Map::new();
+        for version in ["1.0.0", "1.2.0", "2.0.0"] {
+            let hash = publish(root, "api", version, &[("file.tmpl", "mkdir: api\n")]);
+            versions.insert(version.to_string(), serde_json::json!({ "sha256": hash }));
+        }
+        write_index(root, serde_json::json!({ "api": { "versions": versions } }));
+        let registry = dir_registry(root, Vec::new());
+
+        let listing = registry.lookup("api", &VersionReq::parse("^1").unwrap()).unwrap().unwrap();
+        assert_eq!(listing.version, Some(Version::new(1, 2, 0)));
+        assert_eq!(listing.path, "api/1.2.0");
+        assert!(listing.release.is_some());
+
+        let error = registry.lookup("api", &VersionReq::parse("^3").unwrap()).err().unwrap();
+        assert!(error.to_string().contains("no version matching ^3"), "{}", error);
+        assert!(registry.lookup("missing", &VersionReq::STAR).unwrap().is_none());
+    }
+
+    #[test]
+    fn stage_and_install_replace_the_installed_version() {
+        let registry_dir = TempDir::new().unwrap();
+        let root = registry_dir.path();
+        let hash = publish(
+            root,
+            "api",
+            "1.0.0",
+            &[("file.tmpl", "mkdir: api\n"), ("files/src/index.js", "console.log(1)\n")],
+        );
+        write_index(root, serde_json::json!({ "api": { "versions": { "1.0.0": { "sha256": hash } } } }));
+        let registry = dir_registry(root, Vec::new());
+        let listing = registry.lookup("api", &VersionReq::STAR).unwrap().unwrap();
+
+        let store = TempDir::new().unwrap();
+        let staging = store.path().join("downloads/api");
+        let record = registry.stage("api", &listing, &staging).unwrap();
+        assert_eq!(record.hash, hash);
+        assert_eq!(record.version, Some(Version::new(1, 0, 0)));
+        assert!(staging.join(lock::INSTALL_FILE).is_file());
+        assert_eq!(fs::read_to_string(staging.join("files/src/index.js")).unwrap(), "console.log(1)\n");
+
+        let dest = store.path().join("templates/api");
+        let backup = store.path().join("replaced/api");
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("old.txt"), "old").unwrap();
+        replace_dir(&staging, &dest, &backup).unwrap();
+        assert!(!staging.exists());
+        assert!(!backup.exists());
+        assert!(!dest.join("old.txt").exists());
+        assert_eq!(lock::tree_hash(&dest).unwrap(), hash);
+    }
+
+    #[test]
+    fn stage_refuses_content_that_does_not_match_the_index() {
+        let registry_dir = TempDir::new().unwrap();
+        let root = registry_dir.path();
+        publish(root, "api", "1.0.0", &[("file.tmpl", "mkdir: api\n")]);
+        let other = format!("sha256:{}", "0".repeat(64));
+        write_index(root, serde_json::json!({ "api": { "versions": { "1.0.0": { "sha256": other } } } }));
+        let registry = dir_registry(root, Vec::new());
+        let listing = registry.lookup("api", &VersionReq::STAR).unwrap().unwrap();
+
+        let store = TempDir::new().unwrap();
+        let error = registry.stage("api", &listing, &store.path().join("api")).err().unwrap();
+        assert!(error.to_string().contains("doesn't match the registry index"), "{}", error);
+    }
+
+    #[test]
+    fn stage_refuses_files_outside_the_template() {
+        let files = HashMap::from([
+            ("evil/file.tmpl".to_string(), "mkdir: x\n".to_string()),
+            ("evil/files/../../escaped.txt".to_string(), "gotcha".to_string()),
+        ]);
+        let registry = Registry::with_sources(vec![Box::new(MemorySource(files))], Vec::new());
+        let listing = registry.lookup("evil", &VersionReq::STAR).unwrap().unwrap();
+
+        let store = TempDir::new().unwrap();
+        let staging = store.path().join("downloads/evil");
+        let error = registry.stage("evil", &listing, &staging).err().unwrap();
+        assert!(error.to_string().contains("points outside the template"), "{}", error);
+        assert!(!store.path().join("escaped.txt").exists());
+    }
+
+    #[test]
+    fn listing_is_only_required_by_templates_that_copy_files() {
+        let files = HashMap::from([
+            ("api/file.tmpl".to_string(), "mkdir: api\n".to_string()),
+            ("app/file.tmpl".to_string(), "copy_tree: . -> app\n".to_string()),
+        ]);
+        let registry = Registry::with_sources(vec![Box::new(UnlistableSource(MemorySource(files)))], Vec::new());
+        let store = TempDir::new().unwrap();
+
+        let listing = registry.lookup("api", &VersionReq::STAR).unwrap().unwrap();
+        let staging = store.path().join("downloads/api");
+        registry.stage("api", &listing, &staging).unwrap();
+        assert!(staging.join("file.tmpl").is_file());
+
+        let listing = registry.lookup("app", &VersionReq::STAR).unwrap().unwrap();
+        let error = registry.stage("app", &listing, &store.path().join("downloads/app")).err().unwrap();
+        assert!(error.to_string().contains("rate limit"), "{}", error);
+    }
+
+    #[test]
+    fn stage_checks_signatures_against_the_trusted_keys() {
+        let registry_dir = TempDir::new().unwrap();
+        let root = registry_dir.path();
+        let hash = publish(root, "api", "1.0.0", &[("file.tmpl", "mkdir: api\n")]);
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let message = signed_message("api", &Version::new(1, 0, 0), &hash);
+        let signature = BASE64.encode(key.sign(message.as_bytes()).to_bytes());
+        write_index(
+            root,
+            serde_json::json!({ "api": { "versions": { "1.0.0": { "sha256": hash, "signature": signature } } } }),
+        );
+        let store = TempDir::new().unwrap();
+
+        let trusted = dir_registry(root, vec![key.verifying_key()]);
+        let listing = trusted.lookup("api", &VersionReq::STAR).unwrap().unwrap();
+        trusted.stage("api", &listing, &store.path().join("trusted")).unwrap();
+
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        let untrusted = dir_registry(root, vec![other]);
+        let listing = untrusted.lookup("api", &VersionReq::STAR).unwrap().unwrap();
+        let error = untrusted.stage("api", &listing, &store.path().join("untrusted")).err().unwrap();
+        assert!(error.to_string().contains("isn't signed by any of the trusted keys"), "{}", error);
+    }
+
+    #[test]
+    fn names_that_leave_the_store_are_refused_before_touching_it() {
+        assert!(staging_dir("..").is_err());
+        let store = TempDir::new().unwrap();
+        let staging = store.path().join("staged");
+        fs::create_dir_all(&staging).unwrap();
+        assert!(install_staged("../x", &staging).is_err());
+        assert!(staging.is_dir());
+    }
+
+    #[test]
+    fn index_entries_must_be_template_names() {
+        let registry_dir = TempDir::new().unwrap();
+        let hash = format!("sha256:{}", "0".repeat(64));
+        write_index(registry_dir.path(), serde_json::json!({ "../api": { "versions": { "1.0.0": { "sha256": hash } } } }));
+        let registry = dir_registry(registry_dir.path(), Vec::new());
+
+        let error = registry.lookup("api", &VersionReq::STAR).err().unwrap();
+        assert!(error.to_string().contains("Invalid template name '../api'"), "{}", error);
+    }
+
+    #[test]
+    fn signatures_are_bound_to_the_name_and_version() {
+        let registry_dir = TempDir::new().unwrap();
+        let root = registry_dir.path();
+        let hash = publish(root, "api", "2.0.0", &[("file.tmpl", "mkdir: api\n")]);
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let store = TempDir::new().unwrap();
+
+        for (name, version) in [("other", "2.0.0"), ("api", "1.0.0")] {
+            let message = signed_message(name, &Version::parse(version).unwrap(), &hash);
+            let signature = BASE64.encode(key.sign(message.as_bytes()).to_bytes());
+            write_index(
+                root,
+                serde_json::json!({ "api": { "versions": { "2.0.0": { "sha256": hash, "signature": signature } } } }),
+            );
+            let registry = dir_registry(root, vec![key.verifying_key()]);
+            let listing = registry.lookup("api", &VersionReq::STAR).unwrap().unwrap();
+            let error = registry.stage("api", &listing, &store.path().join(name)).err().unwrap();
+            assert!(error.to_string().contains("isn't signed by any of the trusted keys"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use crate::config::RegistryConfig;
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use std::cell::OnceCell;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+/// The registry on the tmpl repository's `registry` branch, used when no
+/// registry is configured.
+const DEFAULT_URL: &str = "https://raw.githubusercontent.com/Jamie-Poeffel/tmpl/refs/heads/registry";
+const DEFAULT_TREE_URL: &str = "https://api.github.com/repos/Jamie-Poeffel/tmpl/git/trees/registry?recursive=1";
+
+/// A file being read from a registry.
+pub struct Download {
+    pub reader: Box<dyn Read>,
+    /// The size, if the source knows it up front.
+    pub len: Option<u64>,
+}
+
+/// Somewhere templates can be installed from. Paths are relative to the
+/// registry's root and use `/`.
+pub trait RegistrySource {
+    /// How the source is called in messages and install records.
+    fn name(&self) -> &str;
+
+    /// Opens the file at `path`; `None` if there is no such file.
+    fn open(&self, path: &str) -> Result<Option<Download>, Box<dyn Error>>;
+
+    /// Every file below the directory `path`, relative to it. Empty if the
+    /// directory doesn't exist.
+    fn list(&self, path: &str) -> Result<Vec<String>, Box<dyn Error>>;
+
+    fn exists(&self, path: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.open(path)?.is_some())
+    }
+}
+
+/// Fails unless `path` is a relative path that stays inside the directory
+/// it is joined to: only plain names, no `/` at the start, `.` or `..`.
+pub fn check_relative(path: &str) -> Result<(), String> {
+    let components: Vec<Component> = Path::new(path).components().collect();
+    if components.is_empty() || !components.iter().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(format!("'{}' points outside the template", path));
+    }
+    Ok(())
+}
+
+/// Creates the source a `[[registry]]` entry describes.
+pub fn from_config(config: &RegistryConfig) -> Result<Box<dyn RegistrySource>, String> {
+    let source: Box<dyn RegistrySource> = match (&config.url, &config.path, &config.git) {
+        (Some(url), None, None) => Box::new(HttpSource::new(url, config.tree.clone())),
+        (None, Some(path), None) => Box::new(DirSource::new(path.clone())),
+        (None, None, Some(git)) => Box::new(GitSource::new(git, config.branch.clone())),
+        _ => return Err("A [[registry]] needs exactly one of `url`, `path` and `git`".to_string()),
+    };
+    if config.tree.is_some() && config.url.is_none() {
+        return Err("`tree` only applies to a [[registry]] with a `url`".to_string());
+    }
+    if config.branch.is_some() && config.git.is_none() {
+        return Err("`branch` only applies to a [[registry]] with `git`".to_string());
+    }
+
+    Ok(match &config.name {
+        Some(name) => Box::new(Named { name: name.clone(), source }),
+        None => source,
+    })
+}
+
+pub fn default_source() -> Box<dyn RegistrySource> {
+    Box::new(HttpSource::new(DEFAULT_URL, Some(DEFAULT_TREE_URL.to_string())))
+}
+
+struct Named {
+    name: String,
+    source: Box<dyn RegistrySource>,
+}
+
+impl RegistrySource for Named {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn open(&self, path: &str) -> Result<Option<Download>, Box<dyn Error>> {
+        self.source.open(path)
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.source.list(path)
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, Box<dyn Error>> {
+        self.source.exists(path)
+    }
+}
+
+/// A registry served over HTTP(S). A plain web server can't list a
+/// directory, so the files of `<dir>` are listed one per line in
+/// `<dir>.txt`, unless the registry is on GitHub and `tree_url` points at
+/// its git tree.
+pub struct HttpSource {
+    client: Client,
+    url: String,
+    tree_url: Option<String>,
+    /// Every file in the git tree, fetched once per run as the API is rate
+    /// limited.
+    tree: OnceCell<Result<Vec<String>, String>>,
+}
+
+impl HttpSource {
+    pub fn new(url: &str, tree_url: Option<String>) -> Self {
+        HttpSource {
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            tree_url,
+            tree: OnceCell::new(),
+        }
+    }
+
+    fn list_tree(&self, tree_url: &str, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let tree = self
+            .tree
+            .get_or_init(|| self.fetch_tree(tree_url).map_err(|e| e.to_string()))
+            .as_ref()
+            .map_err(|e| e.clone())?;
+
+        let prefix = format!("{}/", path);
+        let files: Vec<String> = tree
+            .iter()
+            .filter_map(|file| file.strip_prefix(&prefix).map(str::to_string))
+            .collect();
+        for file in &files {
+            check_relative(file)?;
+        }
+        Ok(files)
+    }
+
+    fn fetch_tree(&self, tree_url: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        #[derive(serde::Deserialize)]
+        struct Tree {
+            tree: Vec<TreeEntry>,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct TreeEntry {
+            path: String,
+            #[serde(rename = "type")]
+            kind: String,
+        }
+
+        let listing = self
+            .client
+            .get(tree_url)
+            .header("User-Agent", "tmpl")
+            .send()?
+            .error_for_status()?
+            .text()?;
+        let tree: Tree = serde_json::from_str(&listing)?;
+        Ok(tree
+            .tree
+            .into_iter()
+            .filter(|entry| entry.kind == "blob")
+            .map(|entry| entry.path)
+            .collect())
+    }
+}
+
+impl RegistrySource for HttpSource {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn open(&self, path: &str) -> Result<Option<Download>, Box<dyn Error>> {
+        let response = self.client.get(format!("{}/{}", self.url, path)).send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        Ok(Some(Download {
+            len: response.content_length(),
+            reader: Box::new(response),
+        }))
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        if let Some(tree_url) = &self.tree_url {
+            return self.list_tree(tree_url, path);
+        }
+
+        let Some(mut download) = self.open(&format!("{}.txt", path))? else {
+            return Ok(Vec::new());
+        };
+        let mut text = String::new();
+        download.reader.read_to_string(&mut text)?;
+        let files: Vec<String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        for file in &files {
+            check_relative(file)?;
+        }
+        Ok(files)
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, Box<dyn Error>> {
+        let response = self.client.head(format!("{}/{}", self.url, path)).send()?;
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(format!("{}/{}: {}", self.url, path, status).into()),
+        }
+    }
+}
+
+/// A registry in a directory on this machine.
+pub struct DirSource {
+    root: PathBuf,
+    name: String,
+}
+
+impl DirSource {
+    pub fn new(root: PathBuf) -> Self {
+        DirSource {
+            name: root.display().to_string(),
+            root,
+        }
+    }
+}
+
+impl RegistrySource for DirSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn open(&self, path: &str) -> Result<Option<Download>, Box<dyn Error>> {
+        let file = match File::open(self.root.join(path)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(Download {
+            len: Some(file.metadata()?.len()),
+            reader: Box::new(file),
+        }))
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let dir = self.root.join(path);
+        let mut files = Vec::new();
+        if dir.is_dir() {
+            list_dir(&dir, "", &mut files)?;
+        }
+        Ok(files)
+    }
+}
+
+fn list_dir(dir: &Path, prefix: &str, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            list_dir(&entry.path(), &format!("{}/", path), files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// A registry in a git repository. It is cloned into tmpl's cache the
+/// first time it is used and brought up to date once per run.
+pub struct GitSource {
+    url: String,
+    branch: Option<String>,
+    checkout: DirSource,
+    synced: OnceCell<Result<(), String>>,
+}
+
+impl GitSource {
+    pub fn new(url: &str, branch: Option<String>) -> Self {
+        let dir_name: String = url
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+            .collect();
+        let dir = dirs::cache_dir()
+            .expect("Could not find cache directory")
+            .join("tmpl/registries")
+            .join(dir_name);
+
+        GitSource {
+            url: url.to_string(),
+            branch,
+            checkout: DirSource::new(dir),
+            synced: OnceCell::new(),
+        }
+    }
+
+    fn sync(&self) -> Result<&DirSource, Box<dyn Error>> {
+        self.synced.get_or_init(|| self.fetch()).clone()?;
+        Ok(&self.checkout)
+    }
+
+    /// Clones the repository, or fetches the branch and moves the checkout
+    /// to it if it was cloned before.
+    fn fetch(&self) -> Result<(), String> {
+        let dir = &self.checkout.root;
+        let branch = self.branch.as_deref().unwrap_or("HEAD");
+
+        if dir.join(".git").is_dir() {
+            git(&["-C", &dir.to_string_lossy(), "fetch", "--depth", "1", "origin", branch])?;
+            git(&["-C", &dir.to_string_lossy(), "reset", "--hard", "FETCH_HEAD"])
+        } else {
+            if let Some(parent) = dir.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("Could not create {}: {}", parent.display(), e))?;
+            }
+            let mut args = vec!["clone", "--depth", "1"];
+            if let Some(branch) = &self.branch {
+                args.extend(["--branch", branch]);
+            }
+            let dir = dir.to_string_lossy();
+            args.extend([self.url.as_str(), &dir]);
+            git(&args)
+        }
+    }
+}
+
+fn git(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Could not run git: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+impl RegistrySource for GitSource {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn open(&self, path: &str) -> Result<Option<Download>, Box<dyn Error>> {
+        self.sync()?.open(path)
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.sync()?.list(path)
+    }
+}
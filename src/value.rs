@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The value of a template variable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Number(f64),
+    Bool(bool),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// Reads a bare word as a number or boolean if it is one, and as a string
+    /// otherwise. Only numbers that print back the same way count, so
+    /// `1.10` or `007` stay text.
+    pub fn from_word(word: &str) -> Value {
+        match word {
+            "true" => return Value::Bool(true),
+            "false" => return Value::Bool(false),
+            _ => {}
+        }
+
+        match word.parse::<f64>() {
+            Ok(n) if n.to_string() == word => Value::Number(n),
+            _ => Value::Str(word.to_string()),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "string",
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    /// How a value reads as a condition: empty strings, `false`, `0` and
+    /// empty lists and maps are false, everything else is true.
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Str(s) => !(s.is_empty() || s == "false" || s == "0"),
+            Value::Number(n) => *n != 0.0,
+            Value::Bool(b) => *b,
+            Value::List(items) => !items.is_empty(),
+            Value::Map(entries) => !entries.is_empty(),
+        }
+    }
+
+    pub fn as_number(&self) -> Result<f64, String> {
+        match self {
+            Value::Number(n) => Ok(*n),
+            Value::Str(s) => s.trim().parse().map_err(|_| format!("'{}' is not a number", s)),
+            other => Err(format!("Expected a number, found a {}", other.type_name())),
+        }
+    }
+
+    /// Equality as used by `==`: numbers compare numerically even when one
+    /// side is text, other mixed types compare by how they print.
+    pub fn loose_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(a), b) | (b, Value::Number(a)) => match b.as_number() {
+                Ok(b) => *a == b,
+                Err(_) => false,
+            },
+            (Value::List(a), Value::List(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.loose_eq(b)),
+            (Value::Map(a), Value::Map(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|((ka, va), (kb, vb))| ka == kb && va.loose_eq(vb))
+            }
+            (a, b) => a.to_string() == b.to_string(),
+        }
+    }
+
+    pub fn len(&self) -> Result<usize, String> {
+        match self {
+            Value::Str(s) => Ok(s.chars().count()),
+            Value::List(items) => Ok(items.len()),
+            Value::Map(entries) => Ok(entries.len()),
+            other => Err(format!("A {} has no length", other.type_name())),
+        }
+    }
+
+    /// `value[index]`: a position in a list or a key in a map.
+    pub fn index(&self, index: &Value) -> Result<Value, String> {
+        match self {
+            Value::List(items) => {
+                let position = index.as_number()?;
+                if position < 0.0 || position.fract() != 0.0 {
+                    return Err(format!("List index must be a whole number, found '{}'", index));
+                }
+                items.get(position as usize).cloned().ok_or_else(|| {
+                    format!("Index {} is out of range for a list of {} item(s)", index, items.len())
+                })
+            }
+            Value::Map(_) => self.field(&index.to_string()),
+            other => Err(format!("Cannot index into a {}", other.type_name())),
+        }
+    }
+
+    /// `value.name`: a key in a map.
+    pub fn field(&self, name: &str) -> Result<Value, String> {
+        match self {
+            Value::Map(entries) => entries
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("No key '{}' in map", name)),
+            other => Err(format!("Cannot access '.{}' on a {}", name, other.type_name())),
+        }
+    }
+
+    /// The values a `for:` loop visits: the items of a list or the keys of
+    /// a map.
+    pub fn items(&self) -> Result<Vec<Value>, String> {
+        match self {
+            Value::List(items) => Ok(items.clone()),
+            Value::Map(entries) => Ok(entries.keys().cloned().map(Value::Str).collect()),
+            other => Err(format!("Cannot loop over a {}", other.type_name())),
+        }
+    }
+
+    /// Writes nested values the way they appear in JSON, so lists and maps
+    /// can be dropped into generated config files as they are.
+    fn fmt_nested(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{}", serde_json::Value::String(s.clone())),
+            other => write!(f, "{}", other),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_nested(f)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", serde_json::Value::String(key.clone()))?;
+                    value.fmt_nested(f)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}